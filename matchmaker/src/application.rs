@@ -1,16 +1,18 @@
 use actix::*;
 use actix_web::{dev::Server, web, App, HttpServer};
-use std::net::TcpListener;
+use std::{net::TcpListener, time::Duration};
 use tracing::info;
 
 use crate::{db::DbPool, middleware::Authentication, settings::Settings};
 
 mod client;
+mod matchbox;
 mod moderator;
 mod services;
 
+use matchbox::Rooms;
 use moderator::Moderator;
-use services::{health_check, login, show, useradd, userdel, users};
+use services::{health_check, login, matchbox_room, show, useradd, userdel, users};
 
 pub struct Application {
     port: u16,
@@ -27,7 +29,14 @@ impl Application {
         let port = listener.local_addr().unwrap().port();
         info!("Running on port: {port}");
 
-        let server = create_server_with_pool(listener, pool)?;
+        let grace_period =
+            Duration::from_millis(configuration.application.reconnect_grace_period_ms);
+        let server = create_server_with_pool(
+            listener,
+            pool,
+            grace_period,
+            configuration.application.matchbox,
+        )?;
         Ok(Self { port, server })
     }
 
@@ -43,15 +52,25 @@ impl Application {
 pub fn create_server_with_pool(
     listener: TcpListener,
     pool: DbPool,
+    grace_period: Duration,
+    matchbox: bool,
 ) -> Result<Server, anyhow::Error> {
     let pool = web::Data::new(pool);
-    let moderator = web::Data::new(Moderator::default().start());
+    let moderator = web::Data::new(Moderator::new(grace_period).start());
+    let rooms = web::Data::new(Rooms::default());
     Ok(HttpServer::new(move || {
         App::new()
             .app_data(pool.clone())
             .app_data(moderator.clone())
+            .app_data(rooms.clone())
             .service(health_check)
             .service(web::scope("/ws").service(login).wrap(Authentication))
+            .configure(|cfg| {
+                // matchbox has no authentication
+                if matchbox {
+                    cfg.service(web::scope("/matchbox").service(matchbox_room));
+                }
+            })
             .service(
                 web::scope("/user")
                     .service(useradd)
@@ -1,5 +1,6 @@
 use std::{
     collections::HashMap,
+    net::IpAddr,
     time::{Duration, Instant},
 };
 use uuid::Uuid;
@@ -13,10 +14,10 @@ pub use ws::start;
 use super::moderator::{self, Moderator};
 
 /// How often heartbeat pings are sent
-const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+pub(super) const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
 
 /// How long before lack of client response causes a timeout
-const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+pub(super) const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
 
 #[derive(Debug)]
 pub struct WsClient {
@@ -24,15 +25,35 @@ pub struct WsClient {
     heartbeat: Instant,
     moderator: Addr<Moderator>,
     peers: HashMap<Uuid, Recipient<moderator::Message>>,
+    /// Set when the client closed the websocket, so it won't resume its session.
+    closed_by_client: bool,
+    /// Where the client connected from.
+    ip: Option<IpAddr>,
 }
 
 impl WsClient {
-    pub fn new(id: Uuid, moderator: Addr<Moderator>) -> Self {
+    pub fn new(id: Uuid, moderator: Addr<Moderator>, ip: Option<IpAddr>) -> Self {
         Self {
             id,
             heartbeat: Instant::now(),
             moderator,
             peers: Default::default(),
+            closed_by_client: false,
+            ip,
+        }
+    }
+
+    /// Clients on open networks announce their UDP port without knowing their
+    /// public IP, it's the one they connected from. The id is replaced by the
+    /// authenticated one, so nobody can redirect another peer's packets.
+    fn fill_in_udp_address(&self, msg: &mut webrtc_socket::message::Message) {
+        if let webrtc_socket::message::Message::UdpAddress { id, addr } = msg {
+            *id = self.id;
+            if let Some(ip) = self.ip {
+                if addr.ip().is_unspecified() {
+                    addr.set_ip(ip);
+                }
+            }
         }
     }
 
@@ -69,7 +90,16 @@ impl Handler<moderator::Message> for WsClient {
                 ctx.text(serde_json::to_string(&msg).unwrap());
                 info!("Ok, told her");
             }
-            moderator::Message::Peers(peers) => self.peers = peers,
+            moderator::Message::Peers(peers) => {
+                let ids = peers.keys().copied().collect();
+                self.peers = peers;
+                let msg = webrtc_socket::message::Message::Peers { ids };
+                ctx.text(serde_json::to_string(&msg).unwrap());
+            }
+            moderator::Message::PeerResumed { id, addr } => {
+                info!("Peer {id} resumed");
+                self.peers.insert(id, addr);
+            }
             moderator::Message::PeerDisconnected { id } => {
                 if self.peers.remove(&id).is_some() {
                     let msg = webrtc_socket::message::Message::PeerDisconnected { id };
@@ -125,7 +155,10 @@ impl Actor for WsClient {
         // notify chat server
         let id = self.id;
         info!("{id} disconnected");
-        self.moderator.do_send(moderator::Disconnect { id });
+        self.moderator.do_send(moderator::Disconnect {
+            id,
+            graceful: self.closed_by_client,
+        });
         Running::Stop
     }
 }
@@ -146,8 +179,10 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsClient {
                 if let Ok(msg) = serde_json::from_str::<webrtc_socket::message::Message>(&text) {
                     info!("Normal msg: {:?}", msg);
                 }
-                if let Ok(msg) = serde_json::from_str::<webrtc_socket::message::PeerMessage>(&text)
+                if let Ok(mut msg) =
+                    serde_json::from_str::<webrtc_socket::message::PeerMessage>(&text)
                 {
+                    self.fill_in_udp_address(&mut msg.content);
                     if let Some(peer) = self.peers.get(&msg.peer_id) {
                         peer.send(moderator::Message::PeerMessage(msg.content))
                             .into_actor(self)
@@ -158,6 +193,7 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsClient {
             }
             Ok(ws::Message::Binary(bin)) => ctx.binary(bin),
             Ok(ws::Message::Close(reason)) => {
+                self.closed_by_client = true;
                 ctx.close(reason);
                 ctx.stop();
             }
@@ -0,0 +1,259 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+use actix::prelude::*;
+use actix_web_actors::ws;
+use tracing::{debug, error, info, warn};
+use webrtc_socket::matchbox::{PeerEvent, PeerRequest, PeerSignal};
+
+use super::{
+    client::{CLIENT_TIMEOUT, HEARTBEAT_INTERVAL},
+    moderator::{self, Moderator},
+};
+
+/// One moderator per matchbox room, peers only meet peers of their room. A
+/// room and its moderator are dropped once its last client left.
+#[derive(Debug, Default)]
+pub struct Rooms {
+    rooms: Mutex<HashMap<String, Room>>,
+}
+
+#[derive(Debug)]
+struct Room {
+    moderator: Addr<Moderator>,
+    clients: usize,
+}
+
+impl Rooms {
+    /// Joins `room`, it is created for its first client.
+    pub fn join(rooms: Arc<Self>, room: &str) -> Membership {
+        let moderator = {
+            let mut map = rooms.rooms.lock().unwrap();
+            let entry = map.entry(room.to_owned()).or_insert_with(|| Room {
+                // matchbox clients can't resume their session
+                moderator: Moderator::new(Duration::ZERO).start(),
+                clients: 0,
+            });
+            entry.clients += 1;
+            entry.moderator.clone()
+        };
+        Membership {
+            rooms,
+            room: room.to_owned(),
+            moderator,
+        }
+    }
+
+    /// Rooms with at least one client.
+    pub fn count(&self) -> usize {
+        self.rooms.lock().unwrap().len()
+    }
+
+    fn leave(&self, room: &str) {
+        let mut rooms = self.rooms.lock().unwrap();
+        if let Some(entry) = rooms.get_mut(room) {
+            entry.clients -= 1;
+            if entry.clients == 0 {
+                rooms.remove(room);
+            }
+        }
+    }
+}
+
+/// A client's place in a room, it leaves the room when dropped.
+#[derive(Debug)]
+pub struct Membership {
+    rooms: Arc<Rooms>,
+    room: String,
+    moderator: Addr<Moderator>,
+}
+
+impl Membership {
+    pub fn moderator(&self) -> &Addr<Moderator> {
+        &self.moderator
+    }
+}
+
+impl Drop for Membership {
+    fn drop(&mut self) {
+        self.rooms.leave(&self.room);
+    }
+}
+
+/// Client of a matchbox room, speaks the matchbox protocol instead of
+/// [`webrtc_socket::message::Message`].
+#[derive(Debug)]
+pub struct MatchboxClient {
+    id: Uuid,
+    heartbeat: Instant,
+    room: Membership,
+    peers: HashMap<Uuid, Recipient<moderator::Message>>,
+}
+
+impl MatchboxClient {
+    pub fn new(id: Uuid, room: Membership) -> Self {
+        Self {
+            id,
+            heartbeat: Instant::now(),
+            room,
+            peers: Default::default(),
+        }
+    }
+
+    fn heartbeat(&self, ctx: &mut <Self as Actor>::Context) {
+        ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
+            if Instant::now().duration_since(act.heartbeat) > CLIENT_TIMEOUT {
+                error!("Matchbox client heartbeat failed, disconnecting!");
+                ctx.stop();
+                return;
+            }
+            ctx.ping(b"");
+        });
+    }
+
+    fn send_event(ctx: &mut <Self as Actor>::Context, event: PeerEvent) {
+        match serde_json::to_string(&event) {
+            Ok(text) => ctx.text(text),
+            Err(e) => error!("Failed to encode {event:?}: {e}"),
+        }
+    }
+
+    fn handle_request(&mut self, request: PeerRequest, ctx: &mut <Self as Actor>::Context) {
+        let (receiver, data) = match request {
+            PeerRequest::Signal { receiver, data } => (receiver, data),
+            PeerRequest::KeepAlive => return,
+        };
+        let msg = match data.into_message(self.id) {
+            Ok(msg) => msg,
+            Err(e) => {
+                warn!("Dropping signal from {}: {e}", self.id);
+                return;
+            }
+        };
+        if let Some(peer) = self.peers.get(&receiver) {
+            peer.send(moderator::Message::PeerMessage(msg))
+                .into_actor(self)
+                .then(|_, _, _| fut::ready(()))
+                .wait(ctx);
+        }
+    }
+}
+
+impl Handler<moderator::Message> for MatchboxClient {
+    type Result = ();
+
+    fn handle(&mut self, msg: moderator::Message, ctx: &mut Self::Context) -> Self::Result {
+        match msg {
+            moderator::Message::NewPeer { id, addr } => {
+                self.peers.insert(id, addr);
+                Self::send_event(ctx, PeerEvent::NewPeer(id));
+            }
+            // Newcomers wait for the offers of the peers already in the room
+            moderator::Message::Peers(peers) => self.peers = peers,
+            moderator::Message::PeerResumed { id, addr } => {
+                self.peers.insert(id, addr);
+            }
+            moderator::Message::PeerDisconnected { id } => {
+                if self.peers.remove(&id).is_some() {
+                    Self::send_event(ctx, PeerEvent::PeerLeft(id));
+                }
+            }
+            moderator::Message::PeerMessage(msg) => {
+                debug!(?msg);
+                match PeerSignal::from_message(msg) {
+                    Ok(Some((sender, data))) => {
+                        Self::send_event(ctx, PeerEvent::Signal { sender, data })
+                    }
+                    Ok(None) => {}
+                    Err(e) => warn!("Dropping signal for {}: {e}", self.id),
+                }
+            }
+        }
+    }
+}
+
+impl Actor for MatchboxClient {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.heartbeat(ctx);
+        Self::send_event(ctx, PeerEvent::IdAssigned(self.id));
+
+        info!("Matchbox client {} started", self.id);
+        self.room
+            .moderator()
+            .send(moderator::Connect {
+                id: self.id,
+                addr: ctx.address().recipient(),
+            })
+            .into_actor(self)
+            .then(|res, _act, ctx| {
+                if !matches!(res, Ok(Ok(_))) {
+                    error!("Failed to join the room. Stopping.");
+                    ctx.stop();
+                }
+                fut::ready(())
+            })
+            .wait(ctx);
+    }
+
+    fn stopping(&mut self, _: &mut Self::Context) -> Running {
+        info!("Matchbox client {} disconnected", self.id);
+        self.room.moderator().do_send(moderator::Disconnect {
+            id: self.id,
+            graceful: true,
+        });
+        Running::Stop
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for MatchboxClient {
+    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        debug!(?msg);
+        match msg {
+            Ok(ws::Message::Ping(msg)) => {
+                self.heartbeat = Instant::now();
+                ctx.pong(&msg);
+            }
+            Ok(ws::Message::Pong(_)) => {
+                self.heartbeat = Instant::now();
+            }
+            Ok(ws::Message::Text(text)) => match serde_json::from_str::<PeerRequest>(&text) {
+                Ok(request) => self.handle_request(request, ctx),
+                Err(e) => warn!("Dropping malformed request from {}: {e}", self.id),
+            },
+            Ok(ws::Message::Close(reason)) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
+            _ => ctx.stop(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::Rooms;
+
+    #[actix_web::test]
+    async fn rooms_are_dropped_with_their_last_client() {
+        let rooms = Arc::new(Rooms::default());
+        let alice = Rooms::join(rooms.clone(), "lobby");
+        let bob = Rooms::join(rooms.clone(), "lobby");
+        let eve = Rooms::join(rooms.clone(), "other");
+        assert!(alice.moderator() == bob.moderator());
+        assert_eq!(rooms.count(), 2);
+
+        drop(alice);
+        drop(eve);
+        assert_eq!(rooms.count(), 1);
+        drop(bob);
+        assert_eq!(rooms.count(), 0);
+    }
+}
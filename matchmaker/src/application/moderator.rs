@@ -1,14 +1,22 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
 
 use actix::prelude::*;
 use tracing::info;
 use uuid::Uuid;
 
+/// How long a client that lost its connection may resume its session
+/// before its peers are told that it disconnected.
+pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
 #[derive(Message)]
 #[rtype(result = "()")]
 pub enum Message {
     NewPeer { id: Uuid, addr: Recipient<Message> },
     Peers(HashMap<Uuid, Recipient<Message>>),
+    PeerResumed { id: Uuid, addr: Recipient<Message> },
     PeerDisconnected { id: Uuid },
     PeerMessage(webrtc_socket::message::Message),
 }
@@ -34,11 +42,48 @@ pub struct Connect {
 #[rtype(result = "()")]
 pub struct Disconnect {
     pub id: Uuid,
+    /// The client closed the connection on purpose and won't resume.
+    pub graceful: bool,
+}
+
+/// A client that lost its connection and is still within its grace period.
+struct Absence {
+    handle: SpawnHandle,
+    /// Clients that connected meanwhile and were never told about it.
+    missed: HashSet<Uuid>,
 }
 
-#[derive(Default)]
 pub struct Moderator {
     clients: HashMap<Uuid, Recipient<Message>>,
+    disconnected: HashMap<Uuid, Absence>,
+    grace_period: Duration,
+}
+
+impl Default for Moderator {
+    fn default() -> Self {
+        Self::new(DEFAULT_GRACE_PERIOD)
+    }
+}
+
+impl Moderator {
+    pub fn new(grace_period: Duration) -> Self {
+        Self {
+            clients: Default::default(),
+            disconnected: Default::default(),
+            grace_period,
+        }
+    }
+
+    fn broadcast_disconnect(&mut self, id: Uuid, ctx: &mut Context<Self>) {
+        info!("Client {id} disconnected");
+        for client in self.clients.values() {
+            client
+                .send(Message::PeerDisconnected { id })
+                .into_actor(self)
+                .then(|_, _, _| fut::ready(()))
+                .wait(ctx);
+        }
+    }
 }
 
 impl Actor for Moderator {
@@ -53,6 +98,25 @@ impl Handler<Connect> for Moderator {
         if self.clients.contains_key(&msg.id) {
             return Err(Error::AlreadyConnected);
         }
+        let missed = match self.disconnected.remove(&msg.id) {
+            Some(absence) => {
+                info!("Client {} resumed its session", msg.id);
+                ctx.cancel_future(absence.handle);
+                // Absent clients it missed don't know about it either
+                for (id, other) in self.disconnected.iter_mut() {
+                    if absence.missed.contains(id) {
+                        other.missed.insert(msg.id);
+                    }
+                }
+                Some(absence.missed)
+            }
+            None => {
+                for absence in self.disconnected.values_mut() {
+                    absence.missed.insert(msg.id);
+                }
+                None
+            }
+        };
 
         let peers = self.clients.clone();
 
@@ -67,11 +131,20 @@ impl Handler<Connect> for Moderator {
                     .wait(ctx);
                 continue;
             }
-            client
-                .send(Message::NewPeer {
+            // Clients that joined during the grace period connect to it like
+            // to a new client
+            let notification = match &missed {
+                Some(missed) if !missed.contains(id) => Message::PeerResumed {
+                    id: msg.id,
+                    addr: msg.addr.clone(),
+                },
+                _ => Message::NewPeer {
                     id: msg.id,
                     addr: msg.addr.clone(),
-                })
+                },
+            };
+            client
+                .send(notification)
                 .into_actor(self)
                 .then(|_, _, _| fut::ready(()))
                 .wait(ctx);
@@ -85,17 +158,26 @@ impl Handler<Disconnect> for Moderator {
 
     fn handle(&mut self, msg: Disconnect, ctx: &mut Self::Context) -> Self::Result {
         let id = msg.id;
-        if !self.clients.contains_key(&id) {
+        if self.clients.remove(&id).is_none() {
             return;
         }
 
-        let _ = self.clients.remove(&id);
-        for client in self.clients.values() {
-            client
-                .send(Message::PeerDisconnected { id })
-                .into_actor(self)
-                .then(|_, _, _| fut::ready(()))
-                .wait(ctx);
+        if msg.graceful || self.grace_period.is_zero() {
+            self.broadcast_disconnect(id, ctx);
+            return;
         }
+
+        info!("Client {id} lost its connection, waiting for it to resume");
+        let handle = ctx.run_later(self.grace_period, move |act, ctx| {
+            act.disconnected.remove(&id);
+            act.broadcast_disconnect(id, ctx);
+        });
+        self.disconnected.insert(
+            id,
+            Absence {
+                handle,
+                missed: Default::default(),
+            },
+        );
     }
 }
@@ -8,7 +8,7 @@ use actix_web::{
 };
 use uuid::Uuid;
 
-use super::{client, moderator::Moderator};
+use super::{client, matchbox, moderator::Moderator};
 
 mod user;
 pub use user::*;
@@ -26,6 +26,19 @@ async fn login(
     moderator: web::Data<Addr<Moderator>>,
 ) -> Result<HttpResponse, Error> {
     let user_id = user_id.borrow().unwrap();
-    let websocket = client::WsClient::new(user_id, moderator.get_ref().clone());
+    let ip = req.peer_addr().map(|addr| addr.ip());
+    let websocket = client::WsClient::new(user_id, moderator.get_ref().clone(), ip);
+    client::start(websocket, &req, stream)
+}
+
+#[get("/{room}")]
+async fn matchbox_room(
+    req: HttpRequest,
+    room: web::Path<String>,
+    stream: web::Payload,
+    rooms: web::Data<matchbox::Rooms>,
+) -> Result<HttpResponse, Error> {
+    let room = matchbox::Rooms::join(rooms.into_inner(), &room);
+    let websocket = matchbox::MatchboxClient::new(Uuid::new_v4(), room);
     client::start(websocket, &req, stream)
 }
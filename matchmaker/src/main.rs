@@ -21,6 +21,8 @@ async fn main() -> anyhow::Result<()> {
         application: ApplicationSettings {
             host: "127.0.0.1".to_string(),
             port: 3657,
+            reconnect_grace_period_ms: 10_000,
+            matchbox: std::env::var("MATCHBOX").is_ok(),
         },
     };
     matchmaker::application::Application::build(settings, create_pool(database_url_from_env()))
@@ -6,4 +6,14 @@ pub struct Settings {
 pub struct ApplicationSettings {
     pub port: u16,
     pub host: String,
+    /// How long a client that lost its connection may resume its session.
+    #[serde(default = "default_reconnect_grace_period_ms")]
+    pub reconnect_grace_period_ms: u64,
+    /// Serve the matchbox signaling protocol on `/matchbox/{room}`.
+    #[serde(default)]
+    pub matchbox: bool,
+}
+
+fn default_reconnect_grace_period_ms() -> u64 {
+    10_000
 }
@@ -1,7 +1,8 @@
 use crate::{
-    helper::{enable_tracing, TestAppBuilder, TestUser},
+    helper::{enable_tracing, TestAppBuilder, TestUser, SIGNALING_CLIENTS},
     test_db::TestDb,
 };
+use futures_util::SinkExt as _;
 use matchmaker::{
     authentication::{validate_credentials, Credentials},
     db::{self, actions::find_user_by_name},
@@ -78,17 +79,63 @@ async fn wrong_auth_are_rejected_with_reqwest() -> anyhow::Result<()> {
 }
 
 #[actix_web::test]
-async fn correct_auth_are_redirected() -> anyhow::Result<()> {
+async fn correct_auth_are_accepted() -> anyhow::Result<()> {
     let mut app = TestAppBuilder::new().with_default_user_alice().build();
     app.spawn_app().await;
-    let mut rtc_config = RtcConfigBuilder::new()
-        .address(app.address)
-        .port(app.port)
-        .user("Alice")
-        .password("I like Bob")
-        .build();
-    let (res, _ws) = webrtc_socket::WebRTCSocket::connect(&mut rtc_config).await?;
-
-    assert_eq!(res.status(), 101);
+    for client in SIGNALING_CLIENTS {
+        let rtc_config = RtcConfigBuilder::new()
+            .address(app.address.clone())
+            .port(app.port)
+            .user("Alice")
+            .password("I like Bob")
+            .signaling_client(client)
+            .build();
+        let mut ws = webrtc_socket::WebRTCSocket::connect(&rtc_config).await?;
+        ws.close().await?;
+    }
     Ok(())
 }
+
+#[actix_web::test]
+async fn wrong_auth_is_reported_as_unauthorized() {
+    let mut app = TestAppBuilder::new().with_default_user_alice().build();
+    app.spawn_app().await;
+    for client in SIGNALING_CLIENTS {
+        let rtc_config = RtcConfigBuilder::new()
+            .address(app.address.clone())
+            .port(app.port)
+            .user("Alice")
+            .password("I don't like Bob")
+            .signaling_client(client)
+            .build();
+
+        match webrtc_socket::WebRTCSocket::new(rtc_config).await {
+            Err(webrtc_socket::Error::Unauthorized) => {}
+            res => panic!(
+                "{client:?}: expected Unauthorized, got {:?}",
+                res.map(|_| ())
+            ),
+        }
+    }
+}
+
+#[actix_web::test]
+async fn unreachable_server_is_reported() {
+    for client in SIGNALING_CLIENTS {
+        let rtc_config = RtcConfigBuilder::new()
+            .address("127.0.0.1")
+            .port(1)
+            .user("Alice")
+            .password("I like Bob")
+            .signaling_client(client)
+            .build();
+
+        match webrtc_socket::WebRTCSocket::new(rtc_config).await {
+            Err(webrtc_socket::Error::ServerUnreachable(_)) => {}
+            res => panic!(
+                "{client:?}: expected ServerUnreachable, got {:?}",
+                res.map(|_| ())
+            ),
+        }
+    }
+}
@@ -1,18 +1,53 @@
-use webrtc_socket::{blocking::BlockingWebRTCSocket, peer::RtcConfigBuilder};
+use webrtc_socket::{blocking::BlockingWebRTCSocket, peer::RtcConfigBuilder, Error, SocketEvent};
 
-use crate::helper::enable_tracing;
+use crate::helper::{enable_tracing, TestAppBuilder};
 
-#[test]
-fn blocking() {
+#[actix_web::test]
+async fn blocking() {
     enable_tracing();
+    let mut app = TestAppBuilder::new().with_default_user_alice().build();
+    app.spawn_app().await;
     let rtc_config = RtcConfigBuilder::new()
-        .address("127.0.0.1")
-        .port(3657)
-        .user("bob")
-        .password("bob")
+        .address(app.address)
+        .port(app.port)
+        .user("Alice")
+        .password("I like Bob")
         .build();
-    let mut s = BlockingWebRTCSocket::connect(rtc_config).unwrap();
-    let _ = s.ggrs_socket();
 
-    assert!(true);
+    // the server keeps running on this thread while the socket connects
+    tokio::task::spawn_blocking(move || {
+        let mut s = BlockingWebRTCSocket::connect(rtc_config).unwrap();
+        assert!(s.is_running());
+        assert!(s.last_error().is_none());
+
+        let a = s.ggrs_socket();
+        let b = s.ggrs_socket();
+        assert_eq!(a.players_snapshot(), b.players_snapshot());
+        assert!(s.app_socket::<String>().is_some());
+        assert!(s.app_socket::<String>().is_none());
+
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        let id = s.id();
+        assert!(s.events().contains(&SocketEvent::Connected { id }));
+        s.close();
+    })
+    .await
+    .unwrap();
+}
+
+#[actix_web::test]
+async fn blocking_connect_reports_wrong_credentials() {
+    let mut app = TestAppBuilder::new().with_default_user_alice().build();
+    app.spawn_app().await;
+    let rtc_config = RtcConfigBuilder::new()
+        .address(app.address)
+        .port(app.port)
+        .user("Alice")
+        .password("I like Eve")
+        .build();
+
+    let res = tokio::task::spawn_blocking(move || BlockingWebRTCSocket::connect(rtc_config))
+        .await
+        .unwrap();
+    assert!(matches!(res, Err(Error::Unauthorized)));
 }
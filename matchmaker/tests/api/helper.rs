@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use once_cell::sync::Lazy;
 
 use matchmaker::{
@@ -6,9 +8,17 @@ use matchmaker::{
     settings::{ApplicationSettings, Settings},
 };
 use secrecy::Secret;
+use webrtc_socket::signaling::SignalingClient;
 
 use crate::test_db::{self, TestDb};
 
+/// Grace period for lost connections of the test server.
+pub const GRACE_PERIOD: Duration = Duration::from_secs(1);
+
+/// Every signaling client, tests that talk to the server run with each of them.
+pub const SIGNALING_CLIENTS: [SignalingClient; 2] =
+    [SignalingClient::Awc, SignalingClient::Tungstenite];
+
 static TRACING: Lazy<()> = Lazy::new(|| {
     if std::env::var("RUST_LOG").is_err() {
         std::env::set_var("RUST_LOG", "debug")
@@ -53,6 +63,8 @@ impl TestApp {
             application: ApplicationSettings {
                 host: "127.0.0.1".to_string(),
                 port: 0,
+                reconnect_grace_period_ms: GRACE_PERIOD.as_millis() as u64,
+                matchbox: true,
             },
         };
         let app = application::Application::build(settings, self.db_pool.clone())
@@ -2,6 +2,10 @@ mod authentication;
 mod basic;
 mod blocking;
 mod helper;
+mod matchbox;
+mod players;
+mod raw;
 mod test_db;
+mod udp;
 mod user;
 mod ws;
@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+
+use futures_util::{SinkExt as _, StreamExt as _};
+use tokio::time::{timeout, Duration};
+use uuid::Uuid;
+use webrtc_socket::{
+    matchbox::PeerEvent,
+    peer::RtcConfigBuilder,
+    signaling::{self, Frame, SignalingConnection},
+    signaller::MatchboxSignaller,
+    SocketEvent, WebRTCSocket,
+};
+
+use crate::{
+    helper::{enable_tracing, TestAppBuilder, SIGNALING_CLIENTS},
+    ws::wait_for_event,
+};
+
+/// Recorded between matchbox_socket clients and matchbox_server.
+const TRANSCRIPT: &str = include_str!("../../../webrtc_socket/tests/transcripts/matchbox.txt");
+
+/// Next text frame, answering pings like browsers do.
+async fn next_text(ws: &mut SignalingConnection) -> String {
+    loop {
+        match timeout(Duration::from_secs(5), ws.next()).await {
+            Ok(Some(Ok(Frame::Text(text)))) => return text,
+            Ok(Some(Ok(Frame::Ping(msg)))) => ws.send(Frame::Pong(msg)).await.unwrap(),
+            Ok(Some(Ok(_))) => continue,
+            res => panic!("Did not receive a text frame: {res:?}"),
+        }
+    }
+}
+
+fn replace_ids(line: &str, ids: &HashMap<String, Uuid>) -> String {
+    ids.iter().fold(line.to_owned(), |line, (name, id)| {
+        line.replace(&format!("${name}"), &id.to_string())
+    })
+}
+
+/// Plays the clients' side of the transcript and checks that the server
+/// answers like matchbox_server did.
+#[actix_web::test]
+async fn matchbox_route_replays_transcript() {
+    enable_tracing();
+    let mut app = TestAppBuilder::new().build();
+    app.spawn_app().await;
+    let url = format!("ws://{}:{}/matchbox/next_2", app.address, app.port);
+
+    let mut clients: HashMap<String, SignalingConnection> = HashMap::new();
+    let mut ids: HashMap<String, Uuid> = HashMap::new();
+    for line in TRANSCRIPT.lines() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (direction, json) = line.split_once(' ').unwrap();
+        let name = direction[..direction.len() - 1].to_owned();
+        if !clients.contains_key(&name) {
+            let ws = signaling::connect_url(SIGNALING_CLIENTS[1], &url)
+                .await
+                .unwrap();
+            clients.insert(name.clone(), ws);
+        }
+        let ws = clients.get_mut(&name).unwrap();
+
+        if direction.ends_with('>') {
+            if json == "close" {
+                ws.send(Frame::Close).await.unwrap();
+            } else {
+                ws.send(Frame::Text(replace_ids(json, &ids))).await.unwrap();
+            }
+            continue;
+        }
+        let received = next_text(ws).await;
+        if let Ok(PeerEvent::IdAssigned(id)) = serde_json::from_str(&received) {
+            ids.insert(name, id);
+        }
+        let expected: serde_json::Value = serde_json::from_str(&replace_ids(json, &ids)).unwrap();
+        let received: serde_json::Value = serde_json::from_str(&received).unwrap();
+        assert_eq!(received, expected, "{line}");
+    }
+}
+
+#[actix_web::test]
+async fn sockets_connect_over_matchbox_route() -> anyhow::Result<()> {
+    let mut app = TestAppBuilder::new().build();
+    app.spawn_app().await;
+    let url = format!("ws://{}:{}/matchbox/lobby", app.address, app.port);
+    let rtc_config = RtcConfigBuilder::new().build();
+
+    let signaller = MatchboxSignaller::new(&url).signaling_client(SIGNALING_CLIENTS[0]);
+    let mut alice = WebRTCSocket::with_signaller(rtc_config.clone(), signaller).await?;
+    let alice_id = alice.id();
+    let mut alice_events = alice.subscribe();
+    tokio::task::spawn_local(async move { alice.run().await });
+
+    let signaller = MatchboxSignaller::new(&url).signaling_client(SIGNALING_CLIENTS[1]);
+    let mut bob = WebRTCSocket::with_signaller(rtc_config, signaller).await?;
+    let bob_id = bob.id();
+    let mut bob_events = bob.subscribe();
+    let bob_close = bob.close_handle();
+    tokio::task::spawn_local(async move { bob.run().await });
+
+    wait_for_event(&mut alice_events, SocketEvent::PeerJoined { id: bob_id }).await;
+    wait_for_event(&mut alice_events, SocketEvent::PeerReady { id: bob_id }).await;
+    wait_for_event(&mut bob_events, SocketEvent::PeerReady { id: alice_id }).await;
+
+    bob_close.close();
+    wait_for_event(&mut alice_events, SocketEvent::PeerLeft { id: bob_id }).await;
+    Ok(())
+}
+
+#[actix_web::test]
+async fn matchbox_rooms_are_separate() {
+    let mut app = TestAppBuilder::new().build();
+    app.spawn_app().await;
+    let room = |name: &str| format!("ws://{}:{}/matchbox/{name}", app.address, app.port);
+
+    let mut a = signaling::connect_url(SIGNALING_CLIENTS[1], &room("a"))
+        .await
+        .unwrap();
+    next_text(&mut a).await;
+    let mut b = signaling::connect_url(SIGNALING_CLIENTS[1], &room("b"))
+        .await
+        .unwrap();
+    next_text(&mut b).await;
+
+    let mut other = signaling::connect_url(SIGNALING_CLIENTS[1], &room("a"))
+        .await
+        .unwrap();
+    let other_id = match serde_json::from_str(&next_text(&mut other).await).unwrap() {
+        PeerEvent::IdAssigned(id) => id,
+        event => panic!("Expected IdAssigned, got {event:?}"),
+    };
+    let event: PeerEvent = serde_json::from_str(&next_text(&mut a).await).unwrap();
+    assert_eq!(event, PeerEvent::NewPeer(other_id));
+    assert!(timeout(Duration::from_millis(200), next_text(&mut b))
+        .await
+        .is_err());
+}
@@ -0,0 +1,56 @@
+use ggrs::PlayerType;
+use tokio::time::Duration;
+use webrtc_socket::{peer::RtcConfigBuilder, Error, GgrsSocket, WebRTCSocket};
+
+use crate::helper::{TestAppBuilder, TestUser};
+
+#[actix_web::test]
+async fn wait_for_players() -> anyhow::Result<()> {
+    let alice = TestUser::new("Alice", "I like Bob");
+    let bob = TestUser::new("Bob", "I fancy Alice");
+    let mut app = TestAppBuilder::new().users(vec![alice, bob]).build();
+    app.spawn_app().await;
+
+    let alice_config = RtcConfigBuilder::new()
+        .address(app.address.clone())
+        .port(app.port)
+        .user("Alice")
+        .password("I like Bob")
+        .build();
+    let bob_config = RtcConfigBuilder::new()
+        .address(app.address)
+        .port(app.port)
+        .user("Bob")
+        .password("I fancy Alice")
+        .build();
+
+    let mut alice = WebRTCSocket::new(alice_config).await?;
+    let alice_ggrs = GgrsSocket::new(&mut alice).unwrap();
+    assert!(GgrsSocket::new(&mut alice).is_none());
+    tokio::task::spawn_local(async move { alice.run().await });
+    assert_eq!(alice_ggrs.players_snapshot(), [PlayerType::Local]);
+
+    let mut bob = WebRTCSocket::new(bob_config).await?;
+    let bob_id = bob.id();
+    tokio::task::spawn_local(async move { bob.run().await });
+
+    let players = alice_ggrs
+        .wait_for_players(2, Duration::from_secs(10))
+        .await?;
+    assert_eq!(players.len(), 2);
+    assert!(players.contains(&PlayerType::Remote(bob_id)));
+    assert_eq!(alice_ggrs.players().await?, players);
+    assert_eq!(alice_ggrs.players_snapshot(), players);
+
+    let res = alice_ggrs
+        .wait_for_players(3, Duration::from_millis(100))
+        .await;
+    assert!(matches!(
+        res,
+        Err(Error::PlayersTimeout {
+            expected: 3,
+            ready: 2
+        })
+    ));
+    Ok(())
+}
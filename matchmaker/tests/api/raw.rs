@@ -0,0 +1,85 @@
+use std::time::Instant;
+
+use tokio::{
+    sync::watch,
+    time::{timeout, Duration},
+};
+use uuid::Uuid;
+use webrtc_socket::{
+    peer::{Channel, RtcConfigBuilder},
+    Packet, Payload, RawSocket, WebRTCSocket,
+};
+
+use crate::helper::{TestAppBuilder, TestUser};
+
+async fn wait_until_ready(ready_peers: &mut watch::Receiver<Vec<Uuid>>, id: Uuid) {
+    while !ready_peers.borrow().contains(&id) {
+        ready_peers.changed().await.unwrap();
+    }
+}
+
+async fn receive(socket: &mut RawSocket) -> Packet {
+    timeout(Duration::from_secs(10), socket.recv())
+        .await
+        .expect("no packet in time")
+        .unwrap()
+}
+
+/// Raw bytes go through a `RawSocket` without GGRS.
+#[actix_web::test]
+async fn raw_packets() -> anyhow::Result<()> {
+    let alice = TestUser::new("Alice", "I like Bob");
+    let bob = TestUser::new("Bob", "I fancy Alice");
+    let mut app = TestAppBuilder::new().users(vec![alice, bob]).build();
+    app.spawn_app().await;
+
+    let alice_config = RtcConfigBuilder::new()
+        .address(app.address.clone())
+        .port(app.port)
+        .user("Alice")
+        .password("I like Bob")
+        .build();
+    let bob_config = RtcConfigBuilder::new()
+        .address(app.address)
+        .port(app.port)
+        .user("Bob")
+        .password("I fancy Alice")
+        .build();
+
+    let mut alice = WebRTCSocket::new(alice_config).await?;
+    let alice_id = alice.id();
+    let mut alice_raw = RawSocket::new(&mut alice).unwrap();
+    assert!(RawSocket::new(&mut alice).is_none());
+    let mut alice_ready = alice.ready_peers();
+    tokio::task::spawn_local(async move { alice.run().await });
+
+    let mut bob = WebRTCSocket::new(bob_config).await?;
+    let bob_id = bob.id();
+    let mut bob_raw = RawSocket::new(&mut bob).unwrap();
+    let mut bob_ready = bob.ready_peers();
+    tokio::task::spawn_local(async move { bob.run().await });
+
+    timeout(Duration::from_secs(10), async {
+        wait_until_ready(&mut alice_ready, bob_id).await;
+        wait_until_ready(&mut bob_ready, alice_id).await;
+    })
+    .await?;
+
+    let sent_at = Instant::now();
+    let packet = Packet::new(bob_id, Channel::Reliable, Payload::from_static(b"ping"));
+    assert_eq!(packet.received_at(), None);
+    alice_raw.send_data(packet)?;
+    let packet = receive(&mut bob_raw).await;
+    assert_eq!(packet.id(), alice_id);
+    assert_eq!(packet.channel(), Channel::Reliable);
+    assert!(packet.channel().is_reliable());
+    assert_eq!(packet.payload(), &Payload::from_static(b"ping"));
+    assert!(packet.received_at().unwrap() >= sent_at);
+
+    bob_raw.send_data(Packet::new(alice_id, Channel::Reliable, vec![1, 2, 3]))?;
+    let packet = receive(&mut alice_raw).await;
+    assert_eq!(packet.id(), bob_id);
+    assert_eq!(packet.into_payload(), Payload::from(vec![1, 2, 3]));
+    assert!(alice_raw.receive_data().is_empty());
+    Ok(())
+}
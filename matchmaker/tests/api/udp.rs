@@ -0,0 +1,58 @@
+use std::net::{Ipv4Addr, SocketAddr};
+
+use ggrs::PlayerType;
+use tokio::time::Duration;
+use webrtc_socket::{
+    peer::RtcConfigBuilder,
+    udp::{UdpConfig, UdpPeerSocket},
+};
+
+use crate::helper::{TestAppBuilder, TestUser};
+
+/// Without a public IP the matchmaker tells the other peers the IP we
+/// connected from.
+#[actix_web::test]
+async fn udp_peers_exchange_addresses() -> anyhow::Result<()> {
+    let alice = TestUser::new("Alice", "I like Bob");
+    let bob = TestUser::new("Bob", "I fancy Alice");
+    let mut app = TestAppBuilder::new().users(vec![alice, bob]).build();
+    app.spawn_app().await;
+
+    let alice_config = RtcConfigBuilder::new()
+        .address(app.address.clone())
+        .port(app.port)
+        .user("Alice")
+        .password("I like Bob")
+        .build();
+    let bob_config = RtcConfigBuilder::new()
+        .address(app.address)
+        .port(app.port)
+        .user("Bob")
+        .password("I fancy Alice")
+        .build();
+
+    let mut alice = UdpPeerSocket::new(alice_config, UdpConfig::default()).await?;
+    let alice_id = alice.id();
+    let alice_port = alice.addr().port();
+    assert!(alice.addr().ip().is_unspecified());
+    let alice_ggrs = alice.ggrs_socket();
+    tokio::task::spawn_local(async move { alice.run().await });
+
+    let mut bob = UdpPeerSocket::new(bob_config, UdpConfig::default()).await?;
+    let bob_id = bob.id();
+    let bob_ggrs = bob.ggrs_socket();
+    tokio::task::spawn_local(async move { bob.run().await });
+
+    let players = bob_ggrs
+        .wait_for_players(2, Duration::from_secs(10))
+        .await?;
+    assert!(players.contains(&PlayerType::Remote(alice_id)));
+    let expected = SocketAddr::from((Ipv4Addr::LOCALHOST, alice_port));
+    assert_eq!(bob_ggrs.transport().peer_addr(alice_id), Some(expected));
+
+    let players = alice_ggrs
+        .wait_for_players(2, Duration::from_secs(10))
+        .await?;
+    assert!(players.contains(&PlayerType::Remote(bob_id)));
+    Ok(())
+}
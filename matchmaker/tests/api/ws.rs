@@ -1,40 +1,407 @@
-use crate::helper::{enable_tracing, TestAppBuilder, TestUser};
-use futures_util::{SinkExt as _, StreamExt as _};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use crate::helper::{enable_tracing, TestAppBuilder, TestUser, GRACE_PERIOD, SIGNALING_CLIENTS};
+use actix_web::web::Bytes;
+use futures_util::{future::BoxFuture, SinkExt as _, StreamExt as _};
 use matchmaker::db::actions::display_users;
-use tokio::time::{sleep, Duration};
+use tokio::{
+    select,
+    sync::Notify,
+    time::{sleep, timeout, Duration, Instant},
+};
 use tracing::info;
-use webrtc_socket::{peer::RtcConfigBuilder, WebRTCSocket};
+use uuid::Uuid;
+use webrtc_socket::{
+    message::{Message, PeerMessage},
+    peer::RtcConfigBuilder,
+    signaling::{Frame, SignalingClient, SignalingConnection},
+    signaller::{MatchmakerSignaller, SignalingEvent, SignalingSession, Signaller},
+    EventStream, SocketEvent, WebRTCSocket,
+};
 
 #[actix_web::test]
 async fn client_ping_pong() -> anyhow::Result<()> {
     let mut app = TestAppBuilder::new().with_default_user_alice().build();
     app.spawn_app().await;
-    let mut rtc_config = RtcConfigBuilder::new()
+    for client in SIGNALING_CLIENTS {
+        let rtc_config = RtcConfigBuilder::new()
+            .address(app.address.clone())
+            .port(app.port)
+            .user("Alice")
+            .password("I like Bob")
+            .signaling_client(client)
+            .build();
+        let mut ws = WebRTCSocket::connect(&rtc_config).await?;
+
+        ws.send(Frame::Ping(Bytes::new())).await.unwrap();
+
+        let _ = ws.next().await; // ignore first message with Id
+
+        let got_pong = matches!(ws.next().await, Some(Ok(Frame::Pong(_))));
+        assert!(got_pong, "{client:?} did not receive a pong");
+        ws.close().await?;
+    }
+    Ok(())
+}
+
+/// Next signaling message, skipping pings
+async fn next_message(ws: &mut SignalingConnection) -> Message {
+    loop {
+        match timeout(Duration::from_secs(5), ws.next()).await {
+            Ok(Some(Ok(Frame::Text(msg)))) => {
+                return Message::from_json(msg.as_bytes()).unwrap();
+            }
+            Ok(Some(Ok(_))) => continue,
+            res => panic!("Did not receive a message: {res:?}"),
+        }
+    }
+}
+
+#[actix_web::test]
+async fn close_notifies_peers_immediately() -> anyhow::Result<()> {
+    let alice = TestUser::new("Alice", "I like Bob");
+    let bob = TestUser::new("Bob", "I fancy Alice");
+    let mut app = TestAppBuilder::new().users(vec![alice, bob]).build();
+    app.spawn_app().await;
+
+    let rtc_config = RtcConfigBuilder::new()
+        .address(app.address.clone())
+        .port(app.port)
+        .user("Bob")
+        .password("I fancy Alice")
+        .build();
+    let mut bob = WebRTCSocket::connect(&rtc_config).await?;
+
+    let rtc_config = RtcConfigBuilder::new()
         .address(app.address)
         .port(app.port)
         .user("Alice")
         .password("I like Bob")
         .build();
-    let (_res, mut ws) = WebRTCSocket::connect(&mut rtc_config).await?;
+    let mut alice = WebRTCSocket::new(rtc_config).await?;
 
-    let mut got_pong = false;
-    ws.send(webrtc_socket::ws::Message::Ping(
-        actix_web::web::Bytes::new(),
-    ))
-    .await
-    .unwrap();
+    assert!(matches!(next_message(&mut bob).await, Message::Id(_)));
+    let alice_id = match next_message(&mut bob).await {
+        Message::NewPeer { id } => id,
+        msg => panic!("Expected NewPeer, got {msg:?}"),
+    };
 
-    let _ = ws.next().await; // ignore first message with Id
+    alice.close().await?;
 
-    if let Some(msg) = ws.next().await {
-        match msg {
-            Ok(webrtc_socket::ws::Frame::Pong(_)) => {
-                got_pong = true;
+    match next_message(&mut bob).await {
+        Message::PeerDisconnected { id } => assert_eq!(id, alice_id),
+        msg => panic!("Expected PeerDisconnected, got {msg:?}"),
+    }
+    Ok(())
+}
+
+#[actix_web::test]
+async fn resumed_session_does_not_notify_peers() -> anyhow::Result<()> {
+    let alice = TestUser::new("Alice", "I like Bob");
+    let bob = TestUser::new("Bob", "I fancy Alice");
+    let mut app = TestAppBuilder::new().users(vec![alice, bob]).build();
+    app.spawn_app().await;
+
+    let bob_config = RtcConfigBuilder::new()
+        .address(app.address.clone())
+        .port(app.port)
+        .user("Bob")
+        .password("I fancy Alice")
+        .build();
+    let alice_config = RtcConfigBuilder::new()
+        .address(app.address)
+        .port(app.port)
+        .user("Alice")
+        .password("I like Bob")
+        .build();
+    let mut bob = WebRTCSocket::connect(&bob_config).await?;
+    assert!(matches!(next_message(&mut bob).await, Message::Id(_)));
+
+    let alice = WebRTCSocket::connect(&alice_config).await?;
+    let alice_id = match next_message(&mut bob).await {
+        Message::NewPeer { id } => id,
+        msg => panic!("Expected NewPeer, got {msg:?}"),
+    };
+
+    // connection drops without a Close frame
+    drop(alice);
+    sleep(GRACE_PERIOD / 4).await;
+
+    let mut alice = WebRTCSocket::connect(&alice_config).await?;
+    assert!(matches!(next_message(&mut alice).await, Message::Id(id) if id == alice_id));
+    match next_message(&mut alice).await {
+        Message::Peers { ids } => assert_eq!(ids.len(), 1),
+        msg => panic!("Expected Peers, got {msg:?}"),
+    }
+
+    assert!(
+        timeout(GRACE_PERIOD * 2, next_message(&mut bob))
+            .await
+            .is_err(),
+        "Bob must not be notified about a resumed session"
+    );
+    Ok(())
+}
+
+#[actix_web::test]
+async fn lost_session_is_reported_after_grace_period() -> anyhow::Result<()> {
+    let alice = TestUser::new("Alice", "I like Bob");
+    let bob = TestUser::new("Bob", "I fancy Alice");
+    let mut app = TestAppBuilder::new().users(vec![alice, bob]).build();
+    app.spawn_app().await;
+
+    let bob_config = RtcConfigBuilder::new()
+        .address(app.address.clone())
+        .port(app.port)
+        .user("Bob")
+        .password("I fancy Alice")
+        .build();
+    let alice_config = RtcConfigBuilder::new()
+        .address(app.address)
+        .port(app.port)
+        .user("Alice")
+        .password("I like Bob")
+        .build();
+    let mut bob = WebRTCSocket::connect(&bob_config).await?;
+    assert!(matches!(next_message(&mut bob).await, Message::Id(_)));
+    let alice = WebRTCSocket::connect(&alice_config).await?;
+    assert!(matches!(
+        next_message(&mut bob).await,
+        Message::NewPeer { .. }
+    ));
+
+    let start = Instant::now();
+    drop(alice);
+    assert!(matches!(
+        next_message(&mut bob).await,
+        Message::PeerDisconnected { .. }
+    ));
+    assert!(start.elapsed() >= GRACE_PERIOD);
+    Ok(())
+}
+
+/// Skips events until `expected` arrives
+pub async fn wait_for_event(events: &mut EventStream, expected: SocketEvent) {
+    let found = timeout(Duration::from_secs(10), async {
+        while let Some(event) = events.next().await {
+            if event == expected {
+                return;
             }
-            _ => {}
         }
+        panic!("Event stream ended before {expected:?}");
+    })
+    .await;
+    assert!(found.is_ok(), "Did not receive {expected:?}");
+}
+
+#[actix_web::test]
+async fn events_report_peer_lifecycle() -> anyhow::Result<()> {
+    let alice = TestUser::new("Alice", "I like Bob");
+    let bob = TestUser::new("Bob", "I fancy Alice");
+    let mut app = TestAppBuilder::new().users(vec![alice, bob]).build();
+    app.spawn_app().await;
+
+    let alice_config = RtcConfigBuilder::new()
+        .address(app.address.clone())
+        .port(app.port)
+        .user("Alice")
+        .password("I like Bob")
+        .signaling_client(SignalingClient::Awc)
+        .build();
+    // both clients speak the same protocol
+    let bob_config = RtcConfigBuilder::new()
+        .address(app.address)
+        .port(app.port)
+        .user("Bob")
+        .password("I fancy Alice")
+        .signaling_client(SignalingClient::Tungstenite)
+        .build();
+
+    let mut alice = WebRTCSocket::new(alice_config).await?;
+    let alice_id = alice.id();
+    let mut alice_events = alice.subscribe();
+    tokio::task::spawn_local(async move { alice.run().await });
+    wait_for_event(&mut alice_events, SocketEvent::Connected { id: alice_id }).await;
+
+    let mut bob = WebRTCSocket::new(bob_config).await?;
+    let bob_id = bob.id();
+    let mut bob_events = bob.subscribe();
+    let bob_close = bob.close_handle();
+    tokio::task::spawn_local(async move { bob.run().await });
+
+    wait_for_event(&mut bob_events, SocketEvent::PeerJoined { id: alice_id }).await;
+    wait_for_event(&mut alice_events, SocketEvent::PeerJoined { id: bob_id }).await;
+    wait_for_event(&mut alice_events, SocketEvent::PeerReady { id: bob_id }).await;
+    wait_for_event(&mut bob_events, SocketEvent::PeerReady { id: alice_id }).await;
+
+    bob_close.close();
+    wait_for_event(&mut alice_events, SocketEvent::PeerLeft { id: bob_id }).await;
+    Ok(())
+}
+
+/// Matchmaker logins whose first session can be cut without a Close frame.
+/// Logging in again waits until the test allows it.
+#[derive(Clone)]
+struct FlakySignaller {
+    inner: MatchmakerSignaller,
+    cut: Arc<Notify>,
+    resume: Arc<Notify>,
+    logins: Arc<AtomicUsize>,
+}
+
+impl Signaller for FlakySignaller {
+    fn connect(
+        &self,
+    ) -> BoxFuture<'static, webrtc_socket::Result<(Uuid, Box<dyn SignalingSession + Send>)>> {
+        let first = self.logins.fetch_add(1, Ordering::SeqCst) == 0;
+        let connect = self.inner.connect();
+        let cut = self.cut.clone();
+        let resume = self.resume.clone();
+        Box::pin(async move {
+            if !first {
+                resume.notified().await;
+            }
+            let (id, session) = connect.await?;
+            let session = FlakySession {
+                session: Some(session),
+                cut: first.then_some(cut),
+            };
+            Ok((id, Box::new(session) as Box<dyn SignalingSession + Send>))
+        })
+    }
+}
+
+struct FlakySession {
+    session: Option<Box<dyn SignalingSession + Send>>,
+    cut: Option<Arc<Notify>>,
+}
+
+impl FlakySession {
+    fn lost() -> webrtc_socket::Error {
+        webrtc_socket::Error::ConnectionLost("Cut by the test".to_owned())
     }
-    assert!(got_pong);
+}
+
+impl SignalingSession for FlakySession {
+    fn recv(&mut self) -> BoxFuture<'_, webrtc_socket::Result<Option<SignalingEvent>>> {
+        Box::pin(async move {
+            let session = self.session.as_mut().ok_or_else(Self::lost)?;
+            let cut = match &self.cut {
+                Some(cut) => cut.clone(),
+                None => return session.recv().await,
+            };
+            select! {
+                event = session.recv() => return event,
+                _ = cut.notified() => {}
+            }
+            // Dropping the connection is all the server sees
+            self.session = None;
+            Err(Self::lost())
+        })
+    }
+
+    fn send(&mut self, msg: PeerMessage) -> BoxFuture<'_, webrtc_socket::Result<()>> {
+        Box::pin(async move {
+            match self.session.as_mut() {
+                Some(session) => session.send(msg).await,
+                None => Err(Self::lost()),
+            }
+        })
+    }
+
+    fn close(&mut self) -> BoxFuture<'_, webrtc_socket::Result<()>> {
+        Box::pin(async move {
+            match self.session.as_mut() {
+                Some(session) => session.close().await,
+                None => Ok(()),
+            }
+        })
+    }
+}
+
+#[actix_web::test]
+async fn peers_joining_during_grace_period_connect_after_resume() -> anyhow::Result<()> {
+    let alice = TestUser::new("Alice", "I like Bob");
+    let bob = TestUser::new("Bob", "I fancy Alice");
+    let mut app = TestAppBuilder::new().users(vec![alice, bob]).build();
+    app.spawn_app().await;
+
+    let alice_config = RtcConfigBuilder::new()
+        .address(app.address.clone())
+        .port(app.port)
+        .user("Alice")
+        .password("I like Bob")
+        .build();
+    let bob_config = RtcConfigBuilder::new()
+        .address(app.address)
+        .port(app.port)
+        .user("Bob")
+        .password("I fancy Alice")
+        .build();
+
+    let signaller = FlakySignaller {
+        inner: MatchmakerSignaller::new(alice_config.clone()),
+        cut: Default::default(),
+        resume: Default::default(),
+        logins: Default::default(),
+    };
+    let mut alice = WebRTCSocket::with_signaller(alice_config, signaller.clone()).await?;
+    let alice_id = alice.id();
+    let mut alice_events = alice.subscribe();
+    tokio::task::spawn_local(async move { alice.run().await });
+    wait_for_event(&mut alice_events, SocketEvent::Connected { id: alice_id }).await;
+
+    let lost = Instant::now();
+    signaller.cut.notify_one();
+    wait_for_event(&mut alice_events, SocketEvent::SignalingLost).await;
+    sleep(GRACE_PERIOD / 4).await;
+
+    // Bob joins while the server waits for Alice to resume
+    let mut bob = WebRTCSocket::new(bob_config).await?;
+    let bob_id = bob.id();
+    let mut bob_events = bob.subscribe();
+    tokio::task::spawn_local(async move { bob.run().await });
+    wait_for_event(&mut bob_events, SocketEvent::Connected { id: bob_id }).await;
+
+    signaller.resume.notify_one();
+    wait_for_event(&mut alice_events, SocketEvent::SignalingRestored).await;
+    assert!(
+        lost.elapsed() < GRACE_PERIOD,
+        "Alice has to resume within the grace period"
+    );
+
+    wait_for_event(&mut alice_events, SocketEvent::PeerJoined { id: bob_id }).await;
+    wait_for_event(&mut bob_events, SocketEvent::PeerJoined { id: alice_id }).await;
+    wait_for_event(&mut alice_events, SocketEvent::PeerReady { id: bob_id }).await;
+    wait_for_event(&mut bob_events, SocketEvent::PeerReady { id: alice_id }).await;
+    Ok(())
+}
+
+/// With tungstenite the socket is `Send`, so it can run on any runtime thread.
+#[actix_web::test]
+async fn tungstenite_socket_can_be_spawned() -> anyhow::Result<()> {
+    let mut app = TestAppBuilder::new().with_default_user_alice().build();
+    app.spawn_app().await;
+    let rtc_config = RtcConfigBuilder::new()
+        .address(app.address)
+        .port(app.port)
+        .user("Alice")
+        .password("I like Bob")
+        .signaling_client(SignalingClient::Tungstenite)
+        .build();
+
+    let mut alice = WebRTCSocket::new(rtc_config).await?;
+    let alice_id = alice.id();
+    let mut events = alice.subscribe();
+    let close = alice.close_handle();
+    let run = tokio::spawn(async move { alice.run().await });
+    wait_for_event(&mut events, SocketEvent::Connected { id: alice_id }).await;
+
+    close.close();
+    timeout(Duration::from_secs(10), run).await???;
     Ok(())
 }
 
@@ -0,0 +1,15 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use webrtc_socket::{
+    frame::{self, MessageKind},
+    wire, Payload,
+};
+
+fuzz_target!(|data: &[u8]| {
+    if let Some((kind, payload)) = frame::decode(Payload::copy_from_slice(data)) {
+        if kind == MessageKind::Ggrs {
+            let _ = wire::deserialize::<ggrs::Message>(&payload);
+        }
+    }
+});
@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use webrtc_socket::message::{Message, PeerMessage};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Message::from_json(data);
+    let _ = serde_json::from_slice::<PeerMessage>(data);
+});
@@ -0,0 +1,95 @@
+use std::{marker::PhantomData, sync::Arc};
+
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::sync::mpsc::{error::TrySendError, Sender};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::{
+    broadcast::{Broadcast, Outgoing, Recipients},
+    error::{Error, Result},
+    frame::{MessageKind, PacketReceiver},
+    peer::Channel,
+    wire::{self, DecodeErrors, DecodeSource},
+    Packet, WebRTCSocket,
+};
+
+/// Typed application messages (chat, ready flags, game config, ...) sent
+/// alongside GGRS traffic over the same peer connections.
+///
+/// `T` is usually an enum covering every message the game exchanges.
+#[derive(Debug)]
+pub struct AppSocket<T> {
+    in_app_rx: PacketReceiver,
+    out_data_tx: Sender<Outgoing>,
+    decode_errors: Arc<DecodeErrors>,
+    _message: PhantomData<fn() -> T>,
+}
+
+impl<T> AppSocket<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// `None` if the app messages were already taken, there is only one
+    /// receiver per socket.
+    pub fn new(webrtc_socket: &mut WebRTCSocket) -> Option<Self> {
+        let in_app_rx = webrtc_socket.in_app_rx()?;
+        let out_data_tx = webrtc_socket.out_data_tx();
+        let decode_errors = webrtc_socket.decode_errors();
+        Some(Self::from_channels(in_app_rx, out_data_tx, decode_errors))
+    }
+
+    pub(crate) fn from_channels(
+        in_app_rx: PacketReceiver,
+        out_data_tx: Sender<Outgoing>,
+        decode_errors: Arc<DecodeErrors>,
+    ) -> Self {
+        Self {
+            in_app_rx,
+            out_data_tx,
+            decode_errors,
+            _message: PhantomData,
+        }
+    }
+
+    /// Fails with [`Error::QueueFull`] if the socket does not keep up.
+    pub fn send(&mut self, id: Uuid, channel: Channel, msg: &T) -> Result<()> {
+        let payload = wire::serialize(msg)?;
+        let packet = Packet::with_kind(id, channel, MessageKind::App, payload.into());
+        match self.out_data_tx.try_send(packet.into()) {
+            Err(TrySendError::Full(_)) => Err(Error::QueueFull),
+            // Nobody receives it once the socket is gone, like for a peer that left
+            _ => Ok(()),
+        }
+    }
+
+    /// Sends the message to several peers, it is serialized only once.
+    /// Fails like [`AppSocket::send`].
+    pub fn broadcast(&mut self, to: Recipients, channel: Channel, msg: &T) -> Result<()> {
+        let payload = wire::serialize(msg)?;
+        let broadcast = Broadcast {
+            to,
+            channel,
+            kind: MessageKind::App,
+            payload: payload.into(),
+        };
+        match self.out_data_tx.try_send(broadcast.into()) {
+            Err(TrySendError::Full(_)) => Err(Error::QueueFull),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn receive(&mut self) -> Vec<(Uuid, T)> {
+        let mut messages = vec![];
+        while let Ok(packet) = self.in_app_rx.try_recv() {
+            match wire::deserialize(&packet.payload) {
+                Ok(msg) => messages.push((packet.id, msg)),
+                Err(e) => {
+                    self.decode_errors.record(DecodeSource::App);
+                    warn!("Dropping malformed app message from {}: {e}", packet.id);
+                }
+            }
+        }
+        messages
+    }
+}
@@ -1,36 +1,225 @@
-use std::thread;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
 
-use actix_rt::System;
-use tokio::sync::mpsc;
+use serde::{de::DeserializeOwned, Serialize};
+#[cfg(feature = "awc")]
+use tokio::task::LocalSet;
+use tokio::{
+    runtime, select,
+    sync::{mpsc, oneshot, watch},
+};
+use tracing::error;
+use uuid::Uuid;
 
-use crate::{peer::RtcConfig, GgrsSocket, WebRTCSocket};
+use crate::{
+    broadcast::{Groups, Outgoing},
+    error::{Error, Result},
+    event::EventStream,
+    frame::PacketReceiver,
+    peer::RtcConfig,
+    signaling::SignalingClient,
+    wire::DecodeErrors,
+    AppSocket, GgrsSocket, PeerStats, SocketEvent, WebRTCSocket,
+};
 
+/// Everything the caller needs from a socket running on the background thread.
+struct Handles {
+    id: Uuid,
+    ggrs_socket: GgrsSocket,
+    stats: watch::Receiver<HashMap<Uuid, PeerStats>>,
+    events: EventStream,
+    in_app_rx: PacketReceiver,
+    out_data_tx: mpsc::Sender<Outgoing>,
+    groups: Groups,
+    decode_errors: Arc<DecodeErrors>,
+}
+
+/// State of the background thread, shared with [`BlockingWebRTCSocket`].
+#[derive(Debug, Default)]
+struct Status {
+    running: AtomicBool,
+    last_error: Mutex<Option<Arc<Error>>>,
+}
+
+/// Runs a [`WebRTCSocket`] on a background thread for code that is not async.
 pub struct BlockingWebRTCSocket {
-    ggrs_rx: mpsc::Receiver<GgrsSocket>,
-    tx: mpsc::UnboundedSender<()>,
+    id: Uuid,
+    ggrs_socket: GgrsSocket,
+    stats: watch::Receiver<HashMap<Uuid, PeerStats>>,
+    events: EventStream,
+    in_app_rx: Option<PacketReceiver>,
+    out_data_tx: mpsc::Sender<Outgoing>,
+    groups: Groups,
+    decode_errors: Arc<DecodeErrors>,
+    status: Arc<Status>,
+    shutdown_tx: Option<oneshot::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
 }
 
 impl BlockingWebRTCSocket {
-    pub fn connect(rtc_config: RtcConfig) -> anyhow::Result<Self> {
-        let (tx, mut rx) = mpsc::unbounded_channel();
-        let (ggrs_tx, ggrs_rx) = mpsc::channel(1);
-        thread::spawn(move || {
-            let system = System::new();
-            system.block_on(async move {
-                let mut s = WebRTCSocket::new(rtc_config).await.unwrap();
-                let _ = rx.recv().await;
-                let ggrs_socket = GgrsSocket::new(&mut s);
-                let _ = ggrs_tx.send(ggrs_socket).await;
-                s.run().await.unwrap();
-            });
+    /// Logs in to the signaling server and starts the socket. Fails if the
+    /// login fails, e.g. with [`Error::Unauthorized`].
+    pub fn connect(rtc_config: RtcConfig) -> Result<Self> {
+        let (handles_tx, handles_rx) = oneshot::channel();
+        let (shutdown_tx, mut shutdown_rx) = oneshot::channel();
+        let status = Arc::new(Status::default());
+        let thread_status = status.clone();
+        let thread = thread::spawn(move || {
+            let runtime = match runtime::Builder::new_current_thread().enable_all().build() {
+                Ok(runtime) => runtime,
+                Err(e) => {
+                    let _ = handles_tx.send(Err(Error::Runtime(e)));
+                    return;
+                }
+            };
+            let signaling_client = rtc_config.signaling_client;
+            let task = async move {
+                let mut s = match WebRTCSocket::new(rtc_config).await {
+                    Ok(s) => s,
+                    Err(e) => {
+                        let _ = handles_tx.send(Err(e));
+                        return;
+                    }
+                };
+                let handles = Handles {
+                    id: s.id(),
+                    ggrs_socket: GgrsSocket::new(&mut s).unwrap(),
+                    stats: s.peer_stats(),
+                    events: s.subscribe(),
+                    in_app_rx: s.in_app_rx().unwrap(),
+                    out_data_tx: s.out_data_tx(),
+                    groups: s.groups(),
+                    decode_errors: s.decode_errors(),
+                };
+                thread_status.running.store(true, Ordering::SeqCst);
+                if handles_tx.send(Ok(handles)).is_err() {
+                    let _ = s.close().await;
+                    thread_status.running.store(false, Ordering::SeqCst);
+                    return;
+                }
+                select! {
+                    res = s.run() => {
+                        if let Err(e) = res {
+                            error!("WebRTCSocket stopped: {e}");
+                            *thread_status.last_error.lock().unwrap() = Some(Arc::new(e));
+                        }
+                    }
+                    _ = &mut shutdown_rx => {
+                        if let Err(e) = s.close().await {
+                            error!("Failed to close WebRTCSocket: {e}");
+                        }
+                    }
+                }
+                thread_status.running.store(false, Ordering::SeqCst);
+            };
+            match signaling_client {
+                // awc runs its websocket on a LocalSet
+                #[cfg(feature = "awc")]
+                SignalingClient::Awc => LocalSet::new().block_on(&runtime, task),
+                #[cfg(feature = "tungstenite")]
+                SignalingClient::Tungstenite => runtime.block_on(task),
+            }
         });
 
-        Ok(Self { tx, ggrs_rx })
+        let handles = match handles_rx.blocking_recv() {
+            Ok(Ok(handles)) => handles,
+            Ok(Err(e)) => {
+                let _ = thread.join();
+                return Err(e);
+            }
+            // The thread panicked before the socket was ready
+            Err(_) => {
+                let _ = thread.join();
+                return Err(Error::ChannelClosed);
+            }
+        };
+        Ok(Self {
+            id: handles.id,
+            ggrs_socket: handles.ggrs_socket,
+            stats: handles.stats,
+            events: handles.events,
+            in_app_rx: Some(handles.in_app_rx),
+            out_data_tx: handles.out_data_tx,
+            groups: handles.groups,
+            decode_errors: handles.decode_errors,
+            status,
+            shutdown_tx: Some(shutdown_tx),
+            thread: Some(thread),
+        })
     }
 
-    pub fn ggrs_socket(&mut self) -> GgrsSocket {
-        let _ = self.tx.send(());
-        let ggrs_socket = self.ggrs_rx.blocking_recv().unwrap();
-        ggrs_socket
+    /// Id the signaling server assigned to us.
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    /// Handle for GGRS, all handles share the same socket and its incoming
+    /// messages.
+    pub fn ggrs_socket(&self) -> GgrsSocket {
+        self.ggrs_socket.clone()
+    }
+
+    /// Latest stats of every peer, refreshed by the background thread.
+    pub fn peer_stats(&self) -> HashMap<Uuid, PeerStats> {
+        self.stats.borrow().clone()
+    }
+
+    /// Typed application messages, only available once.
+    pub fn app_socket<T>(&mut self) -> Option<AppSocket<T>>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        let in_app_rx = self.in_app_rx.take()?;
+        Some(AppSocket::from_channels(
+            in_app_rx,
+            self.out_data_tx.clone(),
+            self.decode_errors.clone(),
+        ))
+    }
+
+    /// Named groups of peers for [`crate::Recipients::Group`].
+    pub fn groups(&self) -> Groups {
+        self.groups.clone()
+    }
+
+    /// Events since the last call, never blocks.
+    pub fn events(&mut self) -> Vec<SocketEvent> {
+        std::iter::from_fn(|| self.events.try_recv()).collect()
+    }
+
+    /// Whether the socket is still running on the background thread.
+    pub fn is_running(&self) -> bool {
+        self.status.running.load(Ordering::SeqCst)
+    }
+
+    /// The error that stopped the socket, if any.
+    pub fn last_error(&self) -> Option<Arc<Error>> {
+        self.status.last_error.lock().unwrap().clone()
+    }
+
+    /// Closes all connections and waits for the background thread to finish.
+    pub fn close(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        if let Some(shutdown_tx) = self.shutdown_tx.take() {
+            let _ = shutdown_tx.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for BlockingWebRTCSocket {
+    fn drop(&mut self) {
+        self.shutdown();
     }
 }
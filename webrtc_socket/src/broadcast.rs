@@ -0,0 +1,115 @@
+//! Sending the same payload to several peers, e.g. lobby state for everyone.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, RwLock},
+};
+
+use uuid::Uuid;
+
+use crate::{frame::MessageKind, peer::Channel, Packet, Payload};
+
+/// Who a broadcast goes to. Only ready peers receive it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Recipients {
+    All,
+    /// Everyone but this peer, e.g. the one whose message is relayed.
+    AllExcept(Uuid),
+    /// The members of a [`Groups`] entry at the time the broadcast is sent.
+    Group(String),
+}
+
+impl Recipients {
+    /// The `peers` this goes to.
+    pub(crate) fn select(
+        &self,
+        peers: impl IntoIterator<Item = Uuid>,
+        groups: &Groups,
+    ) -> Vec<Uuid> {
+        let peers = peers.into_iter();
+        match self {
+            Recipients::All => peers.collect(),
+            Recipients::AllExcept(except) => peers.filter(|id| id != except).collect(),
+            Recipients::Group(name) => {
+                let groups = groups.groups.read().unwrap();
+                match groups.get(name) {
+                    Some(members) => peers.filter(|id| members.contains(id)).collect(),
+                    None => vec![],
+                }
+            }
+        }
+    }
+}
+
+/// Named sets of peers for [`Recipients::Group`], shared by all handles of a
+/// socket. Groups are local, the peers don't know which groups they are in.
+#[derive(Debug, Clone, Default)]
+pub struct Groups {
+    groups: Arc<RwLock<HashMap<String, HashSet<Uuid>>>>,
+}
+
+impl Groups {
+    pub fn join(&self, group: &str, id: Uuid) {
+        let mut groups = self.groups.write().unwrap();
+        groups.entry(group.to_owned()).or_default().insert(id);
+    }
+
+    pub fn leave(&self, group: &str, id: Uuid) {
+        let mut groups = self.groups.write().unwrap();
+        if let Some(members) = groups.get_mut(group) {
+            members.remove(&id);
+            if members.is_empty() {
+                groups.remove(group);
+            }
+        }
+    }
+
+    /// Removes the peer from every group, e.g. after it left.
+    pub fn leave_all(&self, id: Uuid) {
+        let mut groups = self.groups.write().unwrap();
+        groups.retain(|_, members| {
+            members.remove(&id);
+            !members.is_empty()
+        });
+    }
+
+    pub fn members(&self, group: &str) -> Vec<Uuid> {
+        let groups = self.groups.read().unwrap();
+        let mut ids: Vec<_> = groups
+            .get(group)
+            .map(|members| members.iter().copied().collect())
+            .unwrap_or_default();
+        ids.sort();
+        ids
+    }
+}
+
+/// A payload for several peers. It is framed once by the run loop and the
+/// frame is shared by all recipients.
+#[derive(Debug, Clone)]
+pub struct Broadcast {
+    pub(crate) to: Recipients,
+    pub(crate) channel: Channel,
+    pub(crate) kind: MessageKind,
+    pub(crate) payload: Payload,
+}
+
+/// What a socket sends. Packets and broadcasts share one queue, so they
+/// leave in the order they were sent.
+#[derive(Debug, Clone)]
+pub enum Outgoing {
+    Packet(Packet),
+    Broadcast(Broadcast),
+}
+
+impl From<Packet> for Outgoing {
+    fn from(packet: Packet) -> Self {
+        Outgoing::Packet(packet)
+    }
+}
+
+impl From<Broadcast> for Outgoing {
+    fn from(broadcast: Broadcast) -> Self {
+        Outgoing::Broadcast(broadcast)
+    }
+}
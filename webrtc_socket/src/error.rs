@@ -0,0 +1,87 @@
+#[cfg(feature = "awc")]
+use awc::{error::WsClientError, http::StatusCode};
+use tokio::sync::mpsc::error::TrySendError;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("The signaling server rejected the credentials")]
+    Unauthorized,
+
+    #[error("Signaling server unreachable: {0}")]
+    ServerUnreachable(String),
+
+    #[error("Signaling connection lost: {0}")]
+    ConnectionLost(String),
+
+    #[error("Signaling protocol violation: {0}")]
+    Protocol(String),
+
+    #[error("Peer connection failed: {0}")]
+    PeerConnection(#[from] webrtc::Error),
+
+    #[error("Network error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Failed to start the async runtime: {0}")]
+    Runtime(std::io::Error),
+
+    #[error("Channel closed")]
+    ChannelClosed,
+
+    #[error("Queue full, the socket is not keeping up")]
+    QueueFull,
+
+    #[error("Peer {0} is not connected")]
+    PeerNotConnected(uuid::Uuid),
+
+    #[error("Only {ready} of {expected} players were ready in time")]
+    PlayersTimeout { expected: usize, ready: usize },
+
+    #[error("Invalid signaling bundle: {0}")]
+    InvalidBundle(String),
+
+    #[error("Failed to encode message: {0}")]
+    Encode(#[from] bincode::Error),
+}
+
+#[cfg(feature = "awc")]
+impl From<WsClientError> for Error {
+    fn from(e: WsClientError) -> Self {
+        match e {
+            WsClientError::InvalidResponseStatus(StatusCode::UNAUTHORIZED) => Error::Unauthorized,
+            WsClientError::SendRequest(e) => Error::ServerUnreachable(e.to_string()),
+            e => Error::Protocol(e.to_string()),
+        }
+    }
+}
+
+#[cfg(feature = "awc")]
+impl From<awc::error::WsProtocolError> for Error {
+    fn from(e: awc::error::WsProtocolError) -> Self {
+        Error::ConnectionLost(e.to_string())
+    }
+}
+
+#[cfg(feature = "tungstenite")]
+impl From<tokio_tungstenite::tungstenite::Error> for Error {
+    fn from(e: tokio_tungstenite::tungstenite::Error) -> Self {
+        Error::ConnectionLost(e.to_string())
+    }
+}
+
+impl<T> From<TrySendError<T>> for Error {
+    fn from(e: TrySendError<T>) -> Self {
+        match e {
+            TrySendError::Full(_) => Error::QueueFull,
+            TrySendError::Closed(_) => Error::ChannelClosed,
+        }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Protocol(e.to_string())
+    }
+}
@@ -0,0 +1,132 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_util::Stream;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use uuid::Uuid;
+
+use crate::peer::PeerState;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SocketEvent {
+    /// [`crate::WebRTCSocket::run`] started, `id` is our own id.
+    Connected {
+        id: Uuid,
+    },
+    /// A peer logged in to the signaling server.
+    PeerJoined {
+        id: Uuid,
+    },
+    /// All data channels to the peer are open, sent again after it reconnected.
+    PeerReady {
+        id: Uuid,
+    },
+    /// The peer logged out or its signaling session expired.
+    PeerLeft {
+        id: Uuid,
+    },
+    PeerStateChanged {
+        id: Uuid,
+        state: PeerState,
+    },
+    /// The peer sent nothing, not even heartbeats, for
+    /// [`crate::peer::Heartbeat::timeout`].
+    PeerUnresponsive {
+        id: Uuid,
+    },
+    /// An unresponsive peer answers again.
+    PeerResponsive {
+        id: Uuid,
+    },
+    /// The connection to the peer could not be restored or it stopped
+    /// answering, and it was removed.
+    PeerFailed {
+        id: Uuid,
+    },
+    /// The signaling connection was lost, we are trying to reconnect.
+    SignalingLost,
+    SignalingRestored,
+}
+
+/// Events of a [`crate::WebRTCSocket`], either polled with
+/// [`EventStream::try_recv`] or consumed as a [`Stream`].
+#[derive(Debug)]
+pub struct EventStream {
+    rx: UnboundedReceiver<SocketEvent>,
+}
+
+impl EventStream {
+    /// Next event if one is waiting, `None` otherwise.
+    pub fn try_recv(&mut self) -> Option<SocketEvent> {
+        self.rx.try_recv().ok()
+    }
+
+    pub async fn recv(&mut self) -> Option<SocketEvent> {
+        self.rx.recv().await
+    }
+}
+
+impl Stream for EventStream {
+    type Item = SocketEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+/// Fans events out to every subscriber that is still listening.
+#[derive(Debug, Default)]
+pub(crate) struct Subscribers {
+    senders: Vec<UnboundedSender<SocketEvent>>,
+}
+
+impl Subscribers {
+    pub(crate) fn subscribe(&mut self) -> EventStream {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.senders.push(tx);
+        EventStream { rx }
+    }
+
+    pub(crate) fn emit(&mut self, event: SocketEvent) {
+        self.senders.retain(|tx| tx.send(event.clone()).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::StreamExt;
+    use uuid::Uuid;
+
+    use super::{SocketEvent, Subscribers};
+
+    #[test]
+    fn events_reach_every_live_subscriber() {
+        let mut subscribers = Subscribers::default();
+        let mut a = subscribers.subscribe();
+        let b = subscribers.subscribe();
+        drop(b);
+
+        let event = SocketEvent::PeerFailed { id: Uuid::new_v4() };
+        subscribers.emit(event.clone());
+        assert_eq!(a.try_recv(), Some(event));
+        assert_eq!(a.try_recv(), None);
+        assert_eq!(subscribers.senders.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn events_can_be_streamed() {
+        let mut subscribers = Subscribers::default();
+        let events = subscribers.subscribe();
+        subscribers.emit(SocketEvent::SignalingLost);
+        subscribers.emit(SocketEvent::SignalingRestored);
+        drop(subscribers);
+
+        let events: Vec<_> = events.collect().await;
+        assert_eq!(
+            events,
+            [SocketEvent::SignalingLost, SocketEvent::SignalingRestored]
+        );
+    }
+}
@@ -0,0 +1,275 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use bytes::{BufMut, BytesMut};
+use tokio::sync::{mpsc::error::TryRecvError, Notify};
+
+use crate::{wire::DecodeErrors, Packet, Payload};
+
+/// One byte header in front of every payload sent over a data channel, so GGRS
+/// traffic and application messages can share the same peer connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum MessageKind {
+    /// Untyped bytes sent via [`crate::WebRTCSocket::send_data`].
+    Raw = 0,
+    /// Bincode encoded [`ggrs::Message`]s, see [`crate::GgrsSocket`].
+    Ggrs = 1,
+    /// Bincode encoded user messages, see [`crate::AppSocket`].
+    App = 2,
+    /// Keep-alive carrying when it was sent, see [`crate::peer::Heartbeat`].
+    Heartbeat = 3,
+    /// A heartbeat's payload sent back on the same data channel, so its sender
+    /// can measure the round trip time.
+    HeartbeatAck = 4,
+}
+
+impl TryFrom<u8> for MessageKind {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(MessageKind::Raw),
+            1 => Ok(MessageKind::Ggrs),
+            2 => Ok(MessageKind::App),
+            3 => Ok(MessageKind::Heartbeat),
+            4 => Ok(MessageKind::HeartbeatAck),
+            _ => Err(value),
+        }
+    }
+}
+
+pub fn encode(kind: MessageKind, payload: &[u8]) -> Payload {
+    let mut frame = BytesMut::with_capacity(payload.len() + 1);
+    frame.put_u8(kind as u8);
+    frame.put_slice(payload);
+    frame.freeze()
+}
+
+/// Splits a received frame into its kind and payload.
+/// Returns `None` for empty frames or unknown kinds.
+pub fn decode(mut frame: Payload) -> Option<(MessageKind, Payload)> {
+    if frame.is_empty() {
+        return None;
+    }
+    let kind = MessageKind::try_from(frame[0]).ok()?;
+    Some((kind, frame.split_off(1)))
+}
+
+/// Received packets of one [`MessageKind`] the application did not take yet.
+#[derive(Debug)]
+struct Queue {
+    packets: Mutex<VecDeque<Packet>>,
+    limit: usize,
+    /// Wakes the [`PacketReceiver`], it is the only one waiting.
+    notify: Notify,
+    /// Set once every [`Inbox`] is gone, nothing arrives after that.
+    closed: AtomicBool,
+}
+
+impl Queue {
+    fn new(limit: usize) -> Arc<Self> {
+        Arc::new(Self {
+            packets: Default::default(),
+            limit,
+            notify: Notify::new(),
+            closed: AtomicBool::new(false),
+        })
+    }
+
+    /// Packets that are worthless once newer ones arrived, e.g. GGRS inputs.
+    fn lossy(packet: &Packet) -> bool {
+        packet.kind == MessageKind::Ggrs || !packet.channel.is_reliable()
+    }
+
+    /// A full queue drops its oldest lossy packet to make room, or the new
+    /// packet if there is none. Returns `false` if a packet was dropped.
+    fn push(&self, packet: Packet) -> bool {
+        let mut packets = self.packets.lock().unwrap();
+        let mut dropped = false;
+        if packets.len() >= self.limit {
+            match packets.iter().position(Self::lossy) {
+                Some(oldest) => {
+                    packets.remove(oldest);
+                    dropped = true;
+                }
+                None => return false,
+            }
+        }
+        packets.push_back(packet);
+        drop(packets);
+        self.notify.notify_one();
+        !dropped
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.notify.notify_one();
+    }
+}
+
+/// Receives the packets of one [`MessageKind`], e.g. from
+/// [`crate::WebRTCSocket::in_data_rx`]. Holds up to
+/// [`crate::peer::QueueLimits::incoming`] packets.
+#[derive(Debug)]
+pub struct PacketReceiver {
+    queue: Arc<Queue>,
+}
+
+impl PacketReceiver {
+    /// Fails with [`TryRecvError::Disconnected`] once the socket is gone and
+    /// every packet was received.
+    pub fn try_recv(&mut self) -> Result<Packet, TryRecvError> {
+        match self.queue.packets.lock().unwrap().pop_front() {
+            Some(packet) => Ok(packet),
+            None if self.queue.closed.load(Ordering::SeqCst) => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// Waits for the next packet, `None` once the socket is gone and every
+    /// packet was received.
+    pub async fn recv(&mut self) -> Option<Packet> {
+        loop {
+            match self.try_recv() {
+                Ok(packet) => return Some(packet),
+                Err(TryRecvError::Disconnected) => return None,
+                Err(TryRecvError::Empty) => self.queue.notify.notified().await,
+            }
+        }
+    }
+}
+
+/// The queues of an [`Inbox`], closed when the last clone is dropped.
+#[derive(Debug)]
+struct Queues {
+    raw: Arc<Queue>,
+    ggrs: Arc<Queue>,
+    app: Arc<Queue>,
+}
+
+impl Drop for Queues {
+    fn drop(&mut self) {
+        for queue in [&self.raw, &self.ggrs, &self.app] {
+            queue.close();
+        }
+    }
+}
+
+/// Routes incoming packets to the receiver of their [`MessageKind`].
+#[derive(Debug, Clone)]
+pub(crate) struct Inbox {
+    queues: Arc<Queues>,
+    errors: Arc<DecodeErrors>,
+}
+
+impl Inbox {
+    /// Inbox with the receivers for raw, GGRS and app packets, each holds up
+    /// to `limit` packets.
+    pub(crate) fn new(limit: usize) -> (Self, PacketReceiver, PacketReceiver, PacketReceiver) {
+        let queues = Queues {
+            raw: Queue::new(limit),
+            ggrs: Queue::new(limit),
+            app: Queue::new(limit),
+        };
+        let receiver = |queue: &Arc<Queue>| PacketReceiver {
+            queue: queue.clone(),
+        };
+        let (raw, ggrs, app) = (
+            receiver(&queues.raw),
+            receiver(&queues.ggrs),
+            receiver(&queues.app),
+        );
+        let inbox = Self {
+            queues: Arc::new(queues),
+            errors: Default::default(),
+        };
+        (inbox, raw, ggrs, app)
+    }
+
+    pub(crate) fn errors(&self) -> &Arc<DecodeErrors> {
+        &self.errors
+    }
+
+    /// Returns `false` if a packet was dropped because the application did
+    /// not keep up with receiving. Unreliable and GGRS packets make room for
+    /// newer ones, reliable packets are only dropped if there is no room.
+    pub(crate) fn deliver(&self, packet: Packet) -> bool {
+        let queue = match packet.kind {
+            MessageKind::Raw => &self.queues.raw,
+            MessageKind::Ggrs => &self.queues.ggrs,
+            MessageKind::App => &self.queues.app,
+            // Only tells the peer that we are still there
+            MessageKind::Heartbeat | MessageKind::HeartbeatAck => return true,
+        };
+        queue.push(packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::{decode, encode, Inbox, MessageKind};
+    use crate::{peer::Channel, Packet, Payload};
+
+    #[test]
+    fn frames_roundtrip() {
+        for kind in [
+            MessageKind::Raw,
+            MessageKind::Ggrs,
+            MessageKind::App,
+            MessageKind::Heartbeat,
+            MessageKind::HeartbeatAck,
+        ] {
+            let frame = encode(kind, b"hello");
+            assert_eq!(decode(frame), Some((kind, Payload::from_static(b"hello"))));
+        }
+    }
+
+    #[test]
+    fn empty_and_unknown_frames_are_rejected() {
+        assert_eq!(decode(Payload::new()), None);
+        assert_eq!(decode(Payload::from_static(&[42, 1, 2, 3])), None);
+    }
+
+    #[test]
+    fn full_receivers_drop_newer_packets() {
+        let (inbox, mut raw_rx, _ggrs_rx, _app_rx) = Inbox::new(2);
+        let packet = |byte| Packet::new(Uuid::nil(), Channel::Reliable, vec![byte]);
+        assert!(inbox.deliver(packet(1)));
+        assert!(inbox.deliver(packet(2)));
+        assert!(!inbox.deliver(packet(3)));
+        assert_eq!(raw_rx.try_recv().unwrap().payload[..], [1]);
+        assert!(inbox.deliver(packet(4)));
+    }
+
+    #[test]
+    fn full_receivers_drop_older_unreliable_packets() {
+        let (inbox, mut raw_rx, _ggrs_rx, _app_rx) = Inbox::new(2);
+        let packet = |channel, byte| Packet::new(Uuid::nil(), channel, vec![byte]);
+        assert!(inbox.deliver(packet(Channel::Unreliable, 1)));
+        assert!(inbox.deliver(packet(Channel::Reliable, 2)));
+        assert!(!inbox.deliver(packet(Channel::Unreliable, 3)));
+        assert!(!inbox.deliver(packet(Channel::Unreliable, 4)));
+        let received: Vec<_> = std::iter::from_fn(|| raw_rx.try_recv().ok())
+            .map(|packet| packet.payload[0])
+            .collect();
+        assert_eq!(received, [2, 4]);
+    }
+
+    #[tokio::test]
+    async fn receivers_end_with_the_inbox() {
+        let (inbox, mut raw_rx, _ggrs_rx, _app_rx) = Inbox::new(2);
+        let peer_inbox = inbox.clone();
+        peer_inbox.deliver(Packet::new(Uuid::nil(), Channel::Reliable, vec![1]));
+        drop((inbox, peer_inbox));
+        assert!(raw_rx.recv().await.is_some());
+        assert!(raw_rx.recv().await.is_none());
+    }
+}
@@ -1,77 +1,134 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
 use ggrs::{Message, PlayerType};
-use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::{
+    sync::{mpsc::Sender, watch},
+    time,
+};
+use tracing::warn;
 use uuid::Uuid;
 
-use crate::{message::StateMessage, Packet, WebRTCSocket};
-
-// impl WebRTCSocket {
-//     #[must_use]
-//     pub fn players(&self) -> Vec<PlayerType<Uuid>> {
-//         // needs to be consistent order across all peers
-//         let mut ids: Vec<Uuid> = self.peers.keys().copied().collect();
-//         ids.push(self.id.to_owned());
-//         ids.sort();
-//         ids.iter()
-//             .map(|id| {
-//                 if id == &self.id {
-//                     PlayerType::Local
-//                 } else {
-//                     PlayerType::Remote(id.to_owned())
-//                 }
-//             })
-//             .collect()
-//     }
-// }
-
-// impl ggrs::NonBlockingSocket<Uuid> for WebRTCSocket {
-//     fn send_to(&mut self, msg: &Message, addr: &Uuid) {
-//         let payload = bincode::serialize(&msg).unwrap();
-//         let payload = bytes::Bytes::from(payload);
-//         let packet = Packet { id: *addr, payload };
-//         self.send_data(packet);
-//     }
+use crate::{
+    broadcast::Outgoing,
+    error::{Error, Result},
+    frame::{MessageKind, PacketReceiver},
+    message::StateMessage,
+    peer::Channel,
+    transport::{ChannelTransport, Transport},
+    wire::{self, DecodeErrors, DecodeSource},
+    Packet, PeerStats, WebRTCSocket,
+};
 
-//     fn receive_all_messages(&mut self) -> Vec<(Uuid, Message)> {
-//         let mut messages = vec![];
-//         for packet in self.receive_data().unwrap() {
-//             let msg = bincode::deserialize(&packet.payload).unwrap();
-//             messages.push((packet.id, msg));
-//         }
-//         messages
-//     }
-// }
-
-#[derive(Debug)]
-pub struct GgrsSocket {
-    id: Uuid,
-    in_data_rx: UnboundedReceiver<Packet>,
-    out_data_tx: UnboundedSender<Packet>,
-    state_tx: UnboundedSender<StateMessage>,
+/// GGRS side of a [`WebRTCSocket`], or of any other [`Transport`].
+///
+/// Clones are handles to the same socket and share one queue of incoming
+/// messages, each message is received by only one of them.
+#[derive(Debug, Clone)]
+pub struct GgrsSocket<T = ChannelTransport> {
+    transport: T,
 }
 
 impl GgrsSocket {
-    pub fn new(webrtc_socket: &mut WebRTCSocket) -> Self {
-        let id = webrtc_socket.id;
-        let in_data_rx = webrtc_socket.in_data_rx().unwrap();
-        let out_data_tx = webrtc_socket.out_data_tx();
-        let state_tx = webrtc_socket.state_tx();
-        Self {
+    /// `None` if the GGRS packets were already taken, clone the first
+    /// [`GgrsSocket`] for more handles.
+    pub fn new(webrtc_socket: &mut WebRTCSocket) -> Option<Self> {
+        let in_ggrs_rx = webrtc_socket.in_ggrs_rx()?;
+        Some(Self::from_channels(
+            webrtc_socket.id,
+            in_ggrs_rx,
+            webrtc_socket.out_data_tx(),
+            webrtc_socket.state_tx(),
+            webrtc_socket.ready_peers(),
+            webrtc_socket.decode_errors(),
+            webrtc_socket.peer_stats(),
+        ))
+    }
+
+    pub(crate) fn from_channels(
+        id: Uuid,
+        in_ggrs_rx: PacketReceiver,
+        out_data_tx: Sender<Outgoing>,
+        state_tx: Sender<StateMessage>,
+        ready_peers: watch::Receiver<Vec<Uuid>>,
+        decode_errors: Arc<DecodeErrors>,
+        stats: watch::Receiver<HashMap<Uuid, PeerStats>>,
+    ) -> Self {
+        Self::with_transport(ChannelTransport::new(
             id,
-            in_data_rx,
+            in_ggrs_rx,
             out_data_tx,
             state_tx,
+            ready_peers,
+            decode_errors,
+            stats,
+        ))
+    }
+}
+
+impl<T: Transport> GgrsSocket<T> {
+    /// GGRS over another transport, e.g. [`crate::udp::UdpTransport`].
+    pub fn with_transport(transport: T) -> Self {
+        Self { transport }
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn decode_errors(&self) -> &DecodeErrors {
+        self.transport.decode_errors()
+    }
+
+    /// Latest stats of every peer, e.g. for a network HUD. Never blocks.
+    pub fn peer_stats(&self) -> HashMap<Uuid, PeerStats> {
+        self.transport.peer_stats()
+    }
+
+    /// Current players, asked from the running socket.
+    pub async fn players(&self) -> Result<Vec<PlayerType<Uuid>>> {
+        let rx = self.transport.request_ready_peers()?;
+        let ids = rx.await.map_err(|_| Error::ChannelClosed)?;
+        Ok(self.player_types(ids))
+    }
+
+    /// Players as of the last update of the socket, never blocks.
+    pub fn players_snapshot(&self) -> Vec<PlayerType<Uuid>> {
+        self.player_types(self.transport.ready_peers().borrow().clone())
+    }
+
+    /// Waits until `n` players including ourselves are ready.
+    pub async fn wait_for_players(
+        &self,
+        n: usize,
+        timeout: Duration,
+    ) -> Result<Vec<PlayerType<Uuid>>> {
+        let mut ready_peers = self.transport.ready_peers().clone();
+        let wait = async {
+            while ready_peers.borrow_and_update().len() + 1 < n {
+                ready_peers
+                    .changed()
+                    .await
+                    .map_err(|_| Error::ChannelClosed)?;
+            }
+            Ok::<_, Error>(())
+        };
+        match time::timeout(timeout, wait).await {
+            Ok(res) => res.map(|()| self.players_snapshot()),
+            Err(_) => Err(Error::PlayersTimeout {
+                expected: n,
+                ready: self.transport.ready_peers().borrow().len() + 1,
+            }),
         }
     }
 
-    pub fn players(&self) -> Vec<PlayerType<Uuid>> {
-        let (tx, mut rx) = mpsc::unbounded_channel::<Vec<Uuid>>();
-        self.state_tx.send(StateMessage::ReadyPeers(tx)).unwrap();
-        let mut ids = rx.blocking_recv().unwrap();
-        ids.push(self.id.to_owned());
+    /// Local and remote players in an order that is the same on all peers.
+    fn player_types(&self, mut ids: Vec<Uuid>) -> Vec<PlayerType<Uuid>> {
+        let own_id = self.transport.id();
+        ids.push(own_id);
         ids.sort();
         ids.iter()
             .map(|id| {
-                if id == &self.id {
+                if id == &own_id {
                     PlayerType::Local
                 } else {
                     PlayerType::Remote(id.to_owned())
@@ -81,23 +138,29 @@ impl GgrsSocket {
     }
 }
 
-impl ggrs::NonBlockingSocket<Uuid> for GgrsSocket {
+impl<T: Transport> ggrs::NonBlockingSocket<Uuid> for GgrsSocket<T> {
     fn send_to(&mut self, msg: &Message, addr: &Uuid) {
-        let payload = bincode::serialize(&msg).unwrap();
-        let payload = bytes::Bytes::from(payload);
-        let packet = Packet { id: *addr, payload };
-        let _ = self.out_data_tx.send(packet);
+        let payload = match wire::serialize(msg) {
+            Ok(payload) => bytes::Bytes::from(payload),
+            Err(e) => {
+                warn!("Failed to serialize ggrs message: {e}");
+                return;
+            }
+        };
+        let packet = Packet::with_kind(*addr, Channel::Unreliable, MessageKind::Ggrs, payload);
+        self.transport.send(packet);
     }
 
     fn receive_all_messages(&mut self) -> Vec<(Uuid, Message)> {
-        let incoming_messages = std::iter::repeat_with(move || self.in_data_rx.try_recv())
-            .take_while(|p| !p.is_err())
-            .map(|p| p.unwrap());
-
         let mut messages = vec![];
-        for packet in incoming_messages {
-            let msg = bincode::deserialize(&packet.payload).unwrap();
-            messages.push((packet.id, msg));
+        for packet in self.transport.receive() {
+            match wire::deserialize(&packet.payload) {
+                Ok(msg) => messages.push((packet.id, msg)),
+                Err(e) => {
+                    self.transport.decode_errors().record(DecodeSource::Ggrs);
+                    warn!("Dropping malformed ggrs message from {}: {e}", packet.id);
+                }
+            }
         }
         messages
     }
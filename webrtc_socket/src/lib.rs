@@ -1,49 +1,168 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::Instant,
+};
 
-use anyhow::anyhow;
-pub use awc::ws;
-use awc::{ws::Codec, BoxedSocket, ClientResponse};
-use futures_util::{SinkExt as _, StreamExt as _};
+use broadcast::{Broadcast, Outgoing};
+use event::{EventStream, Subscribers};
+use frame::{Inbox, MessageKind, PacketReceiver};
+use futures_util::future::BoxFuture;
 use message::{PeerMessage, StateMessage};
-use peer::{Peer, RtcConfig};
+use peer::{
+    Channel, OfferResponse, Peer, PeerState, PeerUpdate, RtcConfig, MAX_ICE_RESTARTS,
+    RESTART_CHECK_INTERVAL,
+};
+use signaling::SignalingConnection;
+use signaller::{MatchmakerSignaller, SignalingEvent, SignalingSession, Signaller};
+use stats::STATS_INTERVAL;
 use tokio::{
     select,
-    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    sync::{
+        mpsc::{self, Receiver, Sender, UnboundedReceiver, UnboundedSender},
+        watch, Notify,
+    },
+    time,
 };
-use tracing::{debug, error, trace};
+use tracing::{debug, info, trace, warn};
 use uuid::Uuid;
 use webrtc::{
     ice_transport::ice_candidate::RTCIceCandidateInit,
     peer_connection::sdp::session_description::RTCSessionDescription,
 };
+use wire::{DecodeErrors, DecodeSource};
 
 use crate::message::Message;
 
+pub mod app_socket;
 pub mod blocking;
+pub mod broadcast;
+pub mod error;
+pub mod event;
+pub mod frame;
 pub mod ggrs_socket;
+pub mod loopback;
+pub mod matchbox;
 pub mod message;
 pub mod peer;
+pub mod raw_socket;
+pub mod signaling;
+pub mod signaller;
+pub mod simulator;
+pub mod stats;
+pub mod transport;
+pub mod udp;
+pub mod wire;
 
+#[cfg(test)]
+mod testing;
+
+pub use app_socket::AppSocket;
+pub use broadcast::{Groups, Recipients};
+pub use error::{Error, Result};
+pub use event::{EventStream, SocketEvent};
 pub use ggrs_socket::GgrsSocket;
+pub use raw_socket::RawSocket;
+pub use stats::PeerStats;
+pub use transport::Transport;
 
 pub type Payload = bytes::Bytes;
 
+/// Raw bytes for or from a peer, see [`RawSocket`].
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Packet {
     id: Uuid,
+    channel: Channel,
+    kind: MessageKind,
     payload: Payload,
+    received_at: Option<Instant>,
+}
+
+impl Packet {
+    /// Packet for the peer `id`.
+    pub fn new(id: Uuid, channel: Channel, payload: impl Into<Payload>) -> Self {
+        Self::with_kind(id, channel, MessageKind::Raw, payload.into())
+    }
+
+    pub(crate) fn with_kind(
+        id: Uuid,
+        channel: Channel,
+        kind: MessageKind,
+        payload: Payload,
+    ) -> Self {
+        Self {
+            id,
+            channel,
+            kind,
+            payload,
+            received_at: None,
+        }
+    }
+
+    /// Stamps the packet as received just now.
+    pub(crate) fn received(mut self) -> Self {
+        self.received_at = Some(Instant::now());
+        self
+    }
+
+    /// The peer the packet is for, or the peer that sent a received packet.
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn channel(&self) -> Channel {
+        self.channel
+    }
+
+    pub fn payload(&self) -> &Payload {
+        &self.payload
+    }
+
+    pub fn into_payload(self) -> Payload {
+        self.payload
+    }
+
+    /// When the socket received the packet, `None` for packets to send.
+    pub fn received_at(&self) -> Option<Instant> {
+        self.received_at
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CloseHandle {
+    close_requested: Arc<Notify>,
+}
+
+impl CloseHandle {
+    /// Makes [`WebRTCSocket::run`] close all connections and return. The
+    /// request is kept until the socket runs, however busy it is.
+    pub fn close(&self) {
+        self.close_requested.notify_one();
+    }
 }
 
 pub struct WebRTCSocket {
     id: Uuid,
     rtc_config: RtcConfig,
     peers: HashMap<Uuid, Peer>,
-    ws: actix_codec::Framed<BoxedSocket, Codec>,
-    in_data_tx: UnboundedSender<Packet>,
-    in_data_rx: Option<UnboundedReceiver<Packet>>,
-    out_data_tx: UnboundedSender<Packet>,
-    out_data_rx: UnboundedReceiver<Packet>,
-    state_tx: UnboundedSender<StateMessage>,
-    state_rx: UnboundedReceiver<StateMessage>,
+    signaller: Arc<dyn Signaller + Send + Sync>,
+    session: Box<dyn SignalingSession + Send>,
+    inbox: Inbox,
+    in_data_rx: Option<PacketReceiver>,
+    in_ggrs_rx: Option<PacketReceiver>,
+    in_app_rx: Option<PacketReceiver>,
+    out_data_tx: Sender<Outgoing>,
+    out_data_rx: Receiver<Outgoing>,
+    groups: Groups,
+    state_tx: Sender<StateMessage>,
+    state_rx: Receiver<StateMessage>,
+    close_requested: Arc<Notify>,
+    peer_updates_tx: UnboundedSender<(Uuid, PeerUpdate)>,
+    peer_updates_rx: UnboundedReceiver<(Uuid, PeerUpdate)>,
+    subscribers: Subscribers,
+    /// Sorted ids of the peers that are ready, see [`Peer::ready`].
+    ready_peers_tx: watch::Sender<Vec<Uuid>>,
+    stats_tx: watch::Sender<HashMap<Uuid, PeerStats>>,
 }
 
 impl std::fmt::Debug for WebRTCSocket {
@@ -57,128 +176,581 @@ impl std::fmt::Debug for WebRTCSocket {
 }
 
 impl WebRTCSocket {
-    pub async fn new(rtc_config: RtcConfig) -> anyhow::Result<Self> {
-        let mut rtc_config = rtc_config;
-        let (_res, mut ws) = WebRTCSocket::connect(&mut rtc_config).await?;
-        let id = if let Some(Ok(ws::Frame::Text(msg))) = ws.next().await {
-            let msg: Message = serde_json::from_slice(&msg)?;
-            if let Message::Id(id) = msg {
-                id
-            } else {
-                return Err(anyhow!("First message must be Id!"));
-            }
-        } else {
-            return Err(anyhow!("Error with Ws connection!"));
-        };
-        let (in_data_tx, in_data_rx) = mpsc::unbounded_channel::<Packet>();
-        let (out_data_tx, out_data_rx) = mpsc::unbounded_channel::<Packet>();
-        let (state_tx, state_rx) = mpsc::unbounded_channel::<StateMessage>();
+    /// Logs in to the matchmaker, see [`MatchmakerSignaller`].
+    pub async fn new(rtc_config: RtcConfig) -> Result<Self> {
+        let signaller = MatchmakerSignaller::new(rtc_config.clone());
+        WebRTCSocket::with_signaller(rtc_config, signaller).await
+    }
+
+    /// Logs in to another signaling backend, `rtc_config` still provides the
+    /// ICE servers and the reconnect backoff.
+    pub async fn with_signaller<S>(rtc_config: RtcConfig, signaller: S) -> Result<Self>
+    where
+        S: Signaller + Send + Sync + 'static,
+    {
+        let (id, session) = signaller.connect().await?;
+        let limits = &rtc_config.queue_limits;
+        let (inbox, in_data_rx, in_ggrs_rx, in_app_rx) = Inbox::new(limits.incoming);
+        let (out_data_tx, out_data_rx) = mpsc::channel::<Outgoing>(limits.outgoing);
+        let (state_tx, state_rx) = mpsc::channel::<StateMessage>(limits.control);
+        let (peer_updates_tx, peer_updates_rx) = mpsc::unbounded_channel();
+        let (ready_peers_tx, _) = watch::channel(vec![]);
+        let (stats_tx, _) = watch::channel(HashMap::new());
         Ok(Self {
             id,
             rtc_config,
             peers: Default::default(),
-            ws,
-            in_data_tx,
+            signaller: Arc::new(signaller),
+            session,
+            inbox,
             in_data_rx: Some(in_data_rx),
+            in_ggrs_rx: Some(in_ggrs_rx),
+            in_app_rx: Some(in_app_rx),
             out_data_tx,
             out_data_rx,
+            groups: Default::default(),
             state_tx,
             state_rx,
+            close_requested: Default::default(),
+            peer_updates_tx,
+            peer_updates_rx,
+            subscribers: Default::default(),
+            ready_peers_tx,
+            stats_tx,
         })
     }
 
+    /// Id the signaling server assigned to us.
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
     fn user(&self) -> &str {
         &self.rtc_config.user
     }
 
-    pub async fn connect(
-        rtc_config: &mut RtcConfig,
-    ) -> Result<(ClientResponse, actix_codec::Framed<BoxedSocket, Codec>), anyhow::Error> {
-        let password = rtc_config.take_password();
-        awc::Client::new()
-            .ws(rtc_config.login_url())
-            .basic_auth(&rtc_config.user, password.as_deref())
-            .connect()
-            .await
-            .map_err(|e| anyhow::anyhow!("Client error: {}", e))
+    pub async fn connect(rtc_config: &RtcConfig) -> Result<SignalingConnection> {
+        signaling::connect(rtc_config).await
+    }
+
+    /// Logs in again with exponential backoff. The signaller has to resume
+    /// the old id, e.g. the matchmaker identifies us by our credentials.
+    fn reconnect(&self) -> BoxFuture<'static, Result<(Uuid, Box<dyn SignalingSession + Send>)>> {
+        signaller::reconnect(self.signaller.clone(), self.rtc_config.reconnect.clone())
     }
 
-    pub async fn run(&mut self) -> Result<(), anyhow::Error> {
+    pub async fn run(&mut self) -> Result<()> {
         debug!("WebRTC run() started");
+        self.subscribers
+            .emit(SocketEvent::Connected { id: self.id });
 
         let (ws_tx, mut ws_rx) = mpsc::unbounded_channel::<PeerMessage>();
+        let mut reconnecting: Option<
+            BoxFuture<'static, Result<(Uuid, Box<dyn SignalingSession + Send>)>>,
+        > = None;
+        let mut unsent = VecDeque::new();
+        let mut stats_interval = time::interval(STATS_INTERVAL);
+        let mut restart_interval = time::interval(RESTART_CHECK_INTERVAL);
+        // `time::interval` panics on the zero interval of disabled heartbeats
+        let heartbeat = &self.rtc_config.heartbeat;
+        let mut heartbeat_interval = heartbeat
+            .enabled()
+            .then(|| time::interval(heartbeat.interval));
+        let close_requested = self.close_requested.clone();
         loop {
+            // Packets wait in their queue until the peers catch up
+            let saturated = self.saturated();
             select! {
-                Some(msg) = ws_rx.recv() => {
+                Some(msg) = ws_rx.recv(), if reconnecting.is_none() => {
                     trace!(?msg);
-                    self.send_text(serde_json::to_string(&msg).unwrap()).await?;
+                    if let Err(e) = self.session.send(msg.clone()).await {
+                        warn!("Signaling connection lost: {e}");
+                        unsent.push_back(msg);
+                        reconnecting = Some(self.signaling_lost());
+                    }
                 }
-                Some(Ok(msg)) = self.ws.next() => {
-                    trace!(?msg);
-                    match msg {
-                        ws::Frame::Text(msg) => {
-                            let msg: Message = serde_json::from_slice(&msg)?;
-                            match msg {
-                                Message::Id(_) => {}
-                                Message::NewPeer { id } => self.new_peer(id, ws_tx.clone()).await?,
-                                Message::PeerDisconnected { id } => {
-                                    debug!("Received PeerDisconnected msg for: {id}");
-                                    let _ = self.peers.remove(&id);
-                                }
-                                Message::Offer { id, offer } =>  self.handle_offer(id, offer, ws_tx.clone()).await?,
-                                Message::Answer { id, answer } => self.handle_answer(id, answer).await?,
-                                Message::IceCandidate { id, candidate } => self.handle_ice_candidate(id, candidate).await?,
-                            }
-                        }
-                        ws::Frame::Close(_) => {
-                            self.ws.close().await?;
+                event = self.session.recv(), if reconnecting.is_none() => match event {
+                    Ok(Some(event)) => {
+                        trace!(?event);
+                        self.handle_signaling_event(event, &ws_tx).await?;
+                        self.publish_ready_peers().await;
+                    }
+                    Ok(None) => {
+                        self.close_peers().await;
+                        break;
+                    }
+                    Err(e) => {
+                        warn!("Signaling connection lost: {e}");
+                        reconnecting = Some(self.signaling_lost());
+                    }
+                },
+                res = async { reconnecting.as_mut().unwrap().await }, if reconnecting.is_some() => {
+                    reconnecting = None;
+                    let (id, session) = res?;
+                    if id != self.id {
+                        return Err(Error::Protocol(format!(
+                            "Resumed session has id {id} instead of {}",
+                            self.id
+                        )));
+                    }
+                    self.session = session;
+                    info!("Signaling connection restored");
+                    self.subscribers.emit(SocketEvent::SignalingRestored);
+                    // Messages stay queued if the new session is lost as well
+                    while let Some(msg) = unsent.front() {
+                        if let Err(e) = self.session.send(msg.clone()).await {
+                            warn!("Signaling connection lost: {e}");
+                            reconnecting = Some(self.signaling_lost());
                             break;
-                        },
-                        ws::Frame::Ping(msg) => self.ws.send(ws::Message::Pong(msg)).await?,
-                        ws::Frame::Pong(_) => {}
-                        ws::Frame::Binary(_) => todo!(),
-                        ws::Frame::Continuation(_) => todo!(),
+                        }
+                        unsent.pop_front();
                     }
                 }
-                Some(packet) = self.out_data_rx.recv() => {
-                    if let Some(peer) = self.peers.get(&packet.id) {
-                        trace!("Send packet with {} bytes to peer {}", packet.payload.len(), packet.id);
-                        peer.send(packet.payload).await?;
+                Some(outgoing) = self.out_data_rx.recv(), if !saturated => {
+                    self.enqueue(outgoing).await;
+                    while !self.saturated() {
+                        match self.out_data_rx.try_recv() {
+                            Ok(outgoing) => self.enqueue(outgoing).await,
+                            Err(_) => break,
+                        }
+                    }
+                    for peer in self.peers.values_mut() {
+                        peer.flush().await;
                     }
                 }
+                Some((id, update)) = self.peer_updates_rx.recv() => {
+                    self.handle_peer_update(id, update, &ws_tx).await?;
+                }
+                _ = stats_interval.tick() => self.publish_stats().await,
+                _ = restart_interval.tick() => self.recover_lost_peers(&ws_tx).await?,
+                _ = async { heartbeat_interval.as_mut().unwrap().tick().await },
+                    if heartbeat_interval.is_some() => self.heartbeat().await,
+                _ = close_requested.notified() => {
+                    self.close().await?;
+                    break;
+                }
                 Some(msg) = self.state_rx.recv() => {
                     match msg {
                         StateMessage::ReadyPeers(tx) => {
-                            let mut peers = vec![];
-
-                            for (&id, peer) in self.peers.iter() {
-                                if peer.ready().await {
-                                    peers.push(id);
-                                }
+                            let _ = tx.send(self.collect_ready_peers().await);
+                        }
+                        StateMessage::OpenChannel { id, channel } => {
+                            if let Err(e) = self.open_channel(id, channel, &ws_tx).await {
+                                warn!("Failed to open channel '{}' to {id}: {e}", channel.label());
                             }
-                            let _ = tx.send(peers);
                         }
+                        StateMessage::Close => {
+                            self.close().await?;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn handle_signaling_event(
+        &mut self,
+        event: SignalingEvent,
+        ws_tx: &mpsc::UnboundedSender<PeerMessage>,
+    ) -> Result<()> {
+        match event {
+            SignalingEvent::Message(msg) => {
+                // A failed handshake is not malformed input, so it is not counted
+                if let Err(e) = self.handle_message(msg, ws_tx.clone()).await {
+                    warn!("Dropping signaling message: {e}");
+                }
+            }
+            SignalingEvent::Invalid(reason) => {
+                self.inbox.errors().record(DecodeSource::Signaling);
+                warn!("Dropping malformed signaling message: {reason}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Closes all peer connections and tells the signaling server that we are
+    /// leaving, so the remaining peers are notified right away.
+    pub async fn close(&mut self) -> Result<()> {
+        debug!("Closing WebRTCSocket {}", self.id);
+        self.close_peers().await;
+        self.session.close().await?;
+        Ok(())
+    }
+
+    async fn close_peers(&mut self) {
+        for (_, peer) in self.peers.drain() {
+            peer.close().await;
+        }
+        self.publish_ready_peers().await;
+        self.publish_stats().await;
+    }
+
+    /// Whether a peer's reliable queue is full, see [`Peer::saturated`].
+    fn saturated(&self) -> bool {
+        self.peers.values().any(Peer::saturated)
+    }
+
+    /// Hands a packet to its peer or a broadcast to its recipients, packets
+    /// for unknown peers are dropped.
+    async fn enqueue(&mut self, outgoing: Outgoing) {
+        match outgoing {
+            Outgoing::Packet(packet) => {
+                if let Some(peer) = self.peers.get_mut(&packet.id) {
+                    trace!(
+                        "Send packet with {} bytes to peer {}",
+                        packet.payload.len(),
+                        packet.id
+                    );
+                    peer.enqueue(packet);
+                }
+            }
+            Outgoing::Broadcast(broadcast) => self.fan_out(broadcast).await,
+        }
+    }
+
+    /// Queues the frame of the broadcast for each of its recipients, the
+    /// payload is only framed once.
+    async fn fan_out(&mut self, broadcast: Broadcast) {
+        let ready = self.collect_ready_peers().await;
+        let recipients = broadcast.to.select(ready, &self.groups);
+        trace!(
+            "Broadcast packet with {} bytes to {} peers",
+            broadcast.payload.len(),
+            recipients.len()
+        );
+        let frame = frame::encode(broadcast.kind, &broadcast.payload);
+        for id in recipients {
+            if let Some(peer) = self.peers.get_mut(&id) {
+                peer.enqueue_frame(broadcast.channel, frame.clone());
+            }
+        }
+    }
+
+    /// Sends keep-alives to the connected peers and handles the ones that
+    /// stopped answering, see [`peer::Heartbeat`]. Peers whose connection was
+    /// lost are still checked, they may never come back.
+    async fn heartbeat(&mut self) {
+        let heartbeat = self.rtc_config.heartbeat.clone();
+        let mut removed = vec![];
+        for (&id, peer) in self.peers.iter_mut() {
+            if !peer.established() {
+                continue;
+            }
+            if peer.connected() {
+                peer.heartbeat();
+                peer.flush().await;
+            }
+            match peer.update_responsive(heartbeat.timeout) {
+                Some(false) => {
+                    warn!("Peer {id} stopped answering");
+                    self.subscribers.emit(SocketEvent::PeerUnresponsive { id });
+                    if heartbeat.remove_unresponsive {
+                        removed.push(id);
                     }
                 }
+                Some(true) => {
+                    info!("Peer {id} answers again");
+                    self.subscribers.emit(SocketEvent::PeerResponsive { id });
+                }
+                None => {}
+            }
+        }
+        for id in removed {
+            self.remove_failed_peer(id).await;
+        }
+        self.publish_ready_peers().await;
+    }
+
+    /// Updates the snapshot handed out by [`WebRTCSocket::peer_stats`].
+    async fn publish_stats(&self) {
+        let mut stats = HashMap::new();
+        for (&id, peer) in self.peers.iter() {
+            stats.insert(id, peer.stats().await);
+        }
+        self.stats_tx.send_replace(stats);
+    }
+
+    /// Stats of every peer, refreshed every [`STATS_INTERVAL`] by
+    /// [`WebRTCSocket::run`].
+    pub fn peer_stats(&self) -> watch::Receiver<HashMap<Uuid, PeerStats>> {
+        self.stats_tx.subscribe()
+    }
+
+    async fn collect_ready_peers(&self) -> Vec<Uuid> {
+        let mut ids = vec![];
+        for (&id, peer) in self.peers.iter() {
+            if peer.ready().await {
+                ids.push(id);
+            }
+        }
+        ids.sort();
+        ids
+    }
+
+    /// Updates the snapshot handed out by [`WebRTCSocket::ready_peers`].
+    async fn publish_ready_peers(&self) {
+        let ids = self.collect_ready_peers().await;
+        if *self.ready_peers_tx.borrow() != ids {
+            self.ready_peers_tx.send_replace(ids);
+        }
+    }
+
+    /// Sorted ids of the peers that are ready, updated by [`WebRTCSocket::run`].
+    pub fn ready_peers(&self) -> watch::Receiver<Vec<Uuid>> {
+        self.ready_peers_tx.subscribe()
+    }
+
+    fn signaling_lost(
+        &mut self,
+    ) -> BoxFuture<'static, Result<(Uuid, Box<dyn SignalingSession + Send>)>> {
+        self.subscribers.emit(SocketEvent::SignalingLost);
+        self.reconnect()
+    }
+
+    async fn handle_peer_update(
+        &mut self,
+        id: Uuid,
+        update: PeerUpdate,
+        ws_tx: &UnboundedSender<PeerMessage>,
+    ) -> Result<()> {
+        match update {
+            PeerUpdate::State(state) => self.handle_peer_state(id, state, ws_tx).await?,
+            PeerUpdate::ChannelOpen(channel) => {
+                if let Some(peer) = self.peers.get_mut(&id) {
+                    // The peer opened another channel, open ours in return
+                    if let Err(e) = peer.open_channel(channel).await {
+                        warn!("Failed to open channel '{}' to {id}: {e}", channel.label());
+                    }
+                }
+            }
+            PeerUpdate::Drained(_) => {
+                if let Some(peer) = self.peers.get_mut(&id) {
+                    peer.flush().await;
+                }
+                return Ok(());
+            }
+        }
+        if let Some(peer) = self.peers.get_mut(&id) {
+            if peer.ready().await && peer.announce_ready() {
+                self.subscribers.emit(SocketEvent::PeerReady { id });
             }
         }
+        self.publish_ready_peers().await;
         Ok(())
     }
 
-    pub fn state_tx(&self) -> UnboundedSender<StateMessage> {
+    /// Restarts ICE when the connection to a peer is lost. A peer that is
+    /// disconnected and then fails only gets one restart, further ones are up
+    /// to [`WebRTCSocket::recover_lost_peers`].
+    async fn handle_peer_state(
+        &mut self,
+        id: Uuid,
+        state: PeerState,
+        ws_tx: &UnboundedSender<PeerMessage>,
+    ) -> Result<()> {
+        let peer = match self.peers.get_mut(&id) {
+            Some(peer) => peer,
+            None => return Ok(()),
+        };
+        self.subscribers
+            .emit(SocketEvent::PeerStateChanged { id, state });
+        match state {
+            PeerState::Connected => peer.connection_restored(),
+            PeerState::Disconnected | PeerState::Failed => {
+                if peer.connection_lost() && peer.restarts_ice() {
+                    info!("Connection to {id} lost, restarting ICE");
+                    let offer = peer.restart_ice().await?;
+                    let _ = ws_tx.send(offer);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Restarts ICE again for peers whose restart timed out and gives up on
+    /// them after [`MAX_ICE_RESTARTS`] restarts. The polite side waits as long
+    /// as the restarts may take and then gives up as well.
+    async fn recover_lost_peers(&mut self, ws_tx: &UnboundedSender<PeerMessage>) -> Result<()> {
+        let timeout = self.rtc_config.ice_restart_timeout;
+        let mut failed = vec![];
+        for (&id, peer) in self.peers.iter_mut() {
+            let lost_for = match peer.lost_for() {
+                Some(lost_for) => lost_for,
+                None => continue,
+            };
+            if !peer.restarts_ice() {
+                if lost_for > timeout * (MAX_ICE_RESTARTS + 1) {
+                    failed.push(id);
+                }
+            } else if lost_for > timeout {
+                if peer.restarts() >= MAX_ICE_RESTARTS {
+                    failed.push(id);
+                } else {
+                    info!("ICE restart with {id} timed out, restarting again");
+                    let offer = peer.restart_ice().await?;
+                    let _ = ws_tx.send(offer);
+                }
+            }
+        }
+        for id in failed {
+            warn!("Giving up on peer {id} after {MAX_ICE_RESTARTS} ICE restarts");
+            self.remove_failed_peer(id).await;
+        }
+        Ok(())
+    }
+
+    /// Closes the connection to a peer we gave up on. It is still logged in,
+    /// so it stays known to the signaling server.
+    async fn remove_failed_peer(&mut self, id: Uuid) {
+        if let Some(peer) = self.peers.remove(&id) {
+            peer.close().await;
+        }
+        self.groups.leave_all(id);
+        self.subscribers.emit(SocketEvent::PeerFailed { id });
+    }
+
+    /// Opens `channel` on an established connection and sends an offer, so
+    /// the peer answers it with [`Peer::handle_offer`].
+    async fn open_channel(
+        &mut self,
+        id: Uuid,
+        channel: Channel,
+        ws_tx: &UnboundedSender<PeerMessage>,
+    ) -> Result<()> {
+        let peer = match self.peers.get_mut(&id) {
+            Some(peer) if peer.connected() => peer,
+            _ => return Err(Error::PeerNotConnected(id)),
+        };
+        if peer.open_channel(channel).await? {
+            let offer = peer.handshake_offer().await?;
+            let _ = ws_tx.send(offer);
+        }
+        Ok(())
+    }
+
+    /// Stream of [`SocketEvent`]s, every subscriber gets all events emitted
+    /// after it subscribed.
+    pub fn subscribe(&mut self) -> EventStream {
+        self.subscribers.subscribe()
+    }
+
+    /// Handle to close the socket while [`WebRTCSocket::run`] is running.
+    pub fn close_handle(&self) -> CloseHandle {
+        CloseHandle {
+            close_requested: self.close_requested.clone(),
+        }
+    }
+
+    async fn handle_message(
+        &mut self,
+        msg: Message,
+        ws_tx: mpsc::UnboundedSender<PeerMessage>,
+    ) -> Result<()> {
+        match msg {
+            Message::Id(_) => {}
+            Message::Peers { ids } => {
+                // Peers that left while our signaling connection was down
+                let left: Vec<_> = self
+                    .peers
+                    .keys()
+                    .filter(|id| !ids.contains(id))
+                    .copied()
+                    .collect();
+                for id in left {
+                    self.peers.remove(&id);
+                    self.groups.leave_all(id);
+                    self.subscribers.emit(SocketEvent::PeerLeft { id });
+                }
+                for id in ids {
+                    if !self.peers.contains_key(&id) {
+                        self.subscribers.emit(SocketEvent::PeerJoined { id });
+                    }
+                }
+            }
+            Message::NewPeer { id } => {
+                self.subscribers.emit(SocketEvent::PeerJoined { id });
+                self.new_peer(id, ws_tx).await?
+            }
+            Message::PeerDisconnected { id } => {
+                debug!("Received PeerDisconnected msg for: {id}");
+                self.groups.leave_all(id);
+                // The peer may already be gone, e.g. after we gave up on it
+                if self.peers.remove(&id).is_some() {
+                    self.subscribers.emit(SocketEvent::PeerLeft { id });
+                }
+            }
+            Message::Offer { id, offer } => self.handle_offer(id, offer, ws_tx).await?,
+            Message::Answer { id, answer } => self.handle_answer(id, answer).await?,
+            Message::IceCandidate { id, candidate } => {
+                self.handle_ice_candidate(id, candidate).await?
+            }
+            Message::EndOfCandidates { id } => {
+                if let Some(peer) = self.peers.get(&id) {
+                    if let Err(e) = peer.end_of_candidates().await {
+                        warn!("Failed to end candidates from {id}: {e}");
+                    }
+                }
+            }
+            Message::UdpAddress { id, .. } => {
+                debug!("Ignoring UDP address of {id}, we connect over WebRTC");
+            }
+        }
+        Ok(())
+    }
+
+    /// Counters for input from peers or the signaling server that was dropped.
+    pub fn decode_errors(&self) -> Arc<DecodeErrors> {
+        self.inbox.errors().clone()
+    }
+
+    pub fn state_tx(&self) -> Sender<StateMessage> {
         self.state_tx.clone()
     }
 
-    pub fn out_data_tx(&self) -> UnboundedSender<Packet> {
+    /// Packets and broadcasts go through the same queue, see [`Outgoing`].
+    pub fn out_data_tx(&self) -> Sender<Outgoing> {
         self.out_data_tx.clone()
     }
 
-    pub fn in_data_rx(&mut self) -> Option<UnboundedReceiver<Packet>> {
+    /// Named groups of peers for [`Recipients::Group`].
+    pub fn groups(&self) -> Groups {
+        self.groups.clone()
+    }
+
+    pub fn in_data_rx(&mut self) -> Option<PacketReceiver> {
         self.in_data_rx.take()
     }
 
-    pub fn send_data(&mut self, packet: Packet) {
-        let _ = self.out_data_tx.send(packet);
+    pub fn in_ggrs_rx(&mut self) -> Option<PacketReceiver> {
+        self.in_ggrs_rx.take()
+    }
+
+    pub fn in_app_rx(&mut self) -> Option<PacketReceiver> {
+        self.in_app_rx.take()
+    }
+
+    /// Fails with [`Error::QueueFull`] if [`WebRTCSocket::run`] does not keep
+    /// up, see [`peer::QueueLimits::outgoing`]. Use a [`RawSocket`] to send
+    /// while the socket runs.
+    pub fn send_data(&mut self, packet: Packet) -> Result<()> {
+        self.out_data_tx.try_send(packet.into())?;
+        Ok(())
+    }
+
+    /// Sends the payload to several of the ready peers. Fails like
+    /// [`WebRTCSocket::send_data`].
+    pub fn broadcast(&mut self, to: Recipients, channel: Channel, payload: Payload) -> Result<()> {
+        let broadcast = Broadcast {
+            to,
+            channel,
+            kind: MessageKind::Raw,
+            payload,
+        };
+        self.out_data_tx.try_send(broadcast.into())?;
+        Ok(())
     }
 
     pub fn receive_data(&mut self) -> Option<impl IntoIterator<Item = Packet> + '_> {
@@ -193,22 +765,35 @@ impl WebRTCSocket {
         }
     }
 
-    async fn send_text(&mut self, msg: String) -> anyhow::Result<()> {
-        Ok(self.ws.send(ws::Message::Text(msg.into())).await?)
+    async fn peer_or_insert(
+        &mut self,
+        id: Uuid,
+        tx: &mpsc::UnboundedSender<PeerMessage>,
+    ) -> Result<&mut Peer> {
+        if !self.peers.contains_key(&id) {
+            let peer = Peer::new(
+                self.id,
+                id,
+                &self.rtc_config,
+                tx.clone(),
+                self.inbox.clone(),
+                self.peer_updates_tx.clone(),
+            )
+            .await?;
+            self.peers.insert(id, peer);
+        }
+        Ok(self.peers.get_mut(&id).unwrap())
     }
 
     async fn new_peer(
         &mut self,
         id: Uuid,
         tx: mpsc::UnboundedSender<PeerMessage>,
-    ) -> anyhow::Result<()> {
+    ) -> Result<()> {
         debug!("New peer with id: {id}");
-        let peer = self.peers.entry(id).or_insert(
-            Peer::new(self.id, id, &self.rtc_config, tx, self.in_data_tx.clone()).await?,
-        );
+        let peer = self.peer_or_insert(id, &tx).await?;
         let offer = peer.handshake_offer().await?;
-        self.send_text(serde_json::to_string(&offer).unwrap())
-            .await?;
+        let _ = tx.send(offer);
         Ok(())
     }
 
@@ -217,14 +802,25 @@ impl WebRTCSocket {
         id: Uuid,
         offer: RTCSessionDescription,
         tx: mpsc::UnboundedSender<PeerMessage>,
-    ) -> anyhow::Result<()> {
+    ) -> Result<()> {
         debug!("{} got offer from {id}. Offer is: {:?}", self.user(), offer);
-        let peer = self.peers.entry(id).or_insert(
-            Peer::new(self.id, id, &self.rtc_config, tx, self.in_data_tx.clone()).await?,
-        );
+        if let Some(peer) = self.peers.get(&id) {
+            match peer.handle_offer(offer.clone()).await? {
+                OfferResponse::Answer(answer) => {
+                    let _ = tx.send(answer);
+                    return Ok(());
+                }
+                OfferResponse::Ignored => return Ok(()),
+                OfferResponse::Reset => {
+                    if let Some(peer) = self.peers.remove(&id) {
+                        peer.close().await;
+                    }
+                }
+            }
+        }
+        let peer = self.peer_or_insert(id, &tx).await?;
         let answer = peer.handshake_accept(offer).await?;
-        self.send_text(serde_json::to_string(&answer).unwrap())
-            .await?;
+        let _ = tx.send(answer);
         Ok(())
     }
 
@@ -232,7 +828,7 @@ impl WebRTCSocket {
         &mut self,
         id: Uuid,
         answer: RTCSessionDescription,
-    ) -> anyhow::Result<()> {
+    ) -> Result<()> {
         debug!(
             "{} got answer from {id}. Answer is: {answer:?}",
             self.user()
@@ -243,17 +839,202 @@ impl WebRTCSocket {
         Ok(())
     }
 
-    async fn handle_ice_candidate(&self, id: Uuid, candidate: String) -> anyhow::Result<()> {
+    async fn handle_ice_candidate(
+        &self,
+        id: Uuid,
+        candidate: RTCIceCandidateInit,
+    ) -> Result<()> {
         if let Some(peer) = self.peers.get(&id) {
-            let candidate = RTCIceCandidateInit {
-                candidate,
-                ..Default::default()
-            };
-            match peer.connection().add_ice_candidate(candidate).await {
-                Ok(_) => {}
-                Err(e) => error!(?e),
+            if let Err(e) = peer.add_ice_candidate(candidate).await {
+                warn!("Failed to add ice candidate from {id}: {e}");
             }
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::{sync::oneshot, time};
+
+    use crate::{
+        message::StateMessage,
+        peer::{Channel, Heartbeat, RtcConfigBuilder},
+        testing::{connect, hub_sockets, resend_until_received, run_until, TIMEOUT},
+        AppSocket, Packet, Payload, RawSocket, Recipients, SocketEvent,
+    };
+
+    #[tokio::test]
+    async fn close_survives_a_full_control_queue() {
+        let [mut socket] = hub_sockets(RtcConfigBuilder::new()).await;
+        let state_tx = socket.state_tx();
+        while state_tx
+            .try_send(StateMessage::ReadyPeers(oneshot::channel().0))
+            .is_ok()
+        {}
+
+        socket.close_handle().close();
+        time::timeout(TIMEOUT, socket.run())
+            .await
+            .expect("Socket did not close")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn channels_open_on_established_peers() {
+        let mut sockets = hub_sockets::<2>(RtcConfigBuilder::new()).await;
+        let (alice_id, bob_id) = (sockets[0].id(), sockets[1].id());
+        let mut alice_raw = RawSocket::new(&mut sockets[0]).unwrap();
+        let mut bob_raw = RawSocket::new(&mut sockets[1]).unwrap();
+        let alice_state = sockets[0].state_tx();
+        let files = Channel::Custom {
+            id: 1,
+            reliable: true,
+        };
+        connect(&mut sockets).await;
+
+        let transfer = async {
+            alice_state
+                .send(StateMessage::OpenChannel {
+                    id: bob_id,
+                    channel: files,
+                })
+                .await
+                .unwrap();
+
+            let chunk = Payload::from_static(b"chunk");
+            let packet = Packet::new(bob_id, files, chunk.clone());
+            let received = resend_until_received(&mut alice_raw, &mut bob_raw, packet).await;
+            assert_eq!(
+                (received.id(), received.channel(), received.payload()),
+                (alice_id, files, &chunk)
+            );
+            // Bob opened the channel in return
+            let packet = Packet::new(alice_id, files, chunk.clone());
+            let received = resend_until_received(&mut bob_raw, &mut alice_raw, packet).await;
+            assert_eq!((received.id(), received.channel()), (bob_id, files));
+        };
+        run_until(&mut sockets, TIMEOUT, transfer).await;
+    }
+
+    #[tokio::test]
+    async fn broadcasts_keep_their_order() {
+        let mut sockets = hub_sockets::<3>(RtcConfigBuilder::new()).await;
+        let (bob_id, carol_id) = (sockets[1].id(), sockets[2].id());
+        let mut apps = sockets
+            .iter_mut()
+            .map(|socket| AppSocket::<String>::new(socket).unwrap())
+            .collect::<Vec<_>>();
+        sockets[0].groups().join("team", bob_id);
+        connect(&mut sockets).await;
+
+        let broadcast = async {
+            let msg = |text: &str| text.to_owned();
+            let alice_app = &mut apps[0];
+            alice_app
+                .send(bob_id, Channel::Reliable, &msg("to bob"))
+                .unwrap();
+            let team = Recipients::Group("team".to_owned());
+            alice_app
+                .broadcast(team, Channel::Reliable, &msg("to the team"))
+                .unwrap();
+            alice_app
+                .send(carol_id, Channel::Reliable, &msg("to carol"))
+                .unwrap();
+            alice_app
+                .broadcast(Recipients::All, Channel::Reliable, &msg("to all"))
+                .unwrap();
+
+            let (mut to_bob, mut to_carol) = (vec![], vec![]);
+            while to_bob.len() < 3 || to_carol.len() < 2 {
+                to_bob.extend(apps[1].receive().into_iter().map(|(_, msg)| msg));
+                to_carol.extend(apps[2].receive().into_iter().map(|(_, msg)| msg));
+                time::sleep(Duration::from_millis(10)).await;
+            }
+            assert_eq!(to_bob, ["to bob", "to the team", "to all"]);
+            assert_eq!(to_carol, ["to carol", "to all"]);
+        };
+        run_until(&mut sockets, TIMEOUT, broadcast).await;
+    }
+
+    /// Connects two sockets, then closes the connection of one of them behind
+    /// the hub's back and waits for the other one to give up on it.
+    async fn gives_up_on_lost_peer(survivor_polite: bool) {
+        let config = RtcConfigBuilder::new().ice_restart_timeout(Duration::from_millis(500));
+        let mut sockets = hub_sockets::<2>(config).await;
+        // The second socket is polite, the survivor goes first
+        if survivor_polite {
+            sockets.swap(0, 1);
+        }
+        let (survivor_id, lost_id) = (sockets[0].id(), sockets[1].id());
+        let mut survivor_events = sockets[0].subscribe();
+        connect(&mut sockets).await;
+        assert_eq!(sockets[0].peers[&lost_id].polite(), survivor_polite);
+
+        // Still logged in, so only the peer connection can tell it is gone
+        sockets[1].peers[&survivor_id].close().await;
+        let failed = async {
+            while let Some(event) = survivor_events.recv().await {
+                if event == (SocketEvent::PeerFailed { id: lost_id }) {
+                    return;
+                }
+            }
+        };
+        run_until(&mut sockets[..1], 3 * TIMEOUT, failed).await;
+        assert!(!sockets[0].peers.contains_key(&lost_id));
+    }
+
+    #[tokio::test]
+    async fn impolite_side_gives_up_after_restarts() {
+        gives_up_on_lost_peer(false).await;
+    }
+
+    #[tokio::test]
+    async fn polite_side_gives_up_on_lost_peer() {
+        gives_up_on_lost_peer(true).await;
+    }
+
+    #[tokio::test]
+    async fn unresponsive_peers_are_removed() {
+        let heartbeat = Heartbeat {
+            interval: Duration::from_millis(100),
+            timeout: Duration::from_millis(500),
+            remove_unresponsive: true,
+        };
+        let mut sockets = hub_sockets::<2>(RtcConfigBuilder::new().heartbeat(heartbeat)).await;
+        let (alice_id, bob_id) = (sockets[0].id(), sockets[1].id());
+        let mut alice_events = sockets[0].subscribe();
+        connect(&mut sockets).await;
+
+        sockets[0].groups().join("team", bob_id);
+        // Bob stops answering, whatever his connection state turns into
+        sockets[1].peers[&alice_id].close().await;
+        let failed = async {
+            let mut unresponsive = false;
+            while let Some(event) = alice_events.recv().await {
+                match event {
+                    SocketEvent::PeerUnresponsive { id } if id == bob_id => unresponsive = true,
+                    SocketEvent::PeerFailed { id } if id == bob_id => return unresponsive,
+                    _ => {}
+                }
+            }
+            false
+        };
+        assert!(run_until(&mut sockets[..1], TIMEOUT, failed).await);
+        assert!(!sockets[0].peers.contains_key(&bob_id));
+        assert!(sockets[0].groups().members("team").is_empty());
+    }
+
+    #[tokio::test]
+    async fn heartbeats_can_be_disabled() {
+        let config = RtcConfigBuilder::new().heartbeat(Heartbeat::disabled());
+        let [mut socket] = hub_sockets(config).await;
+        socket.close_handle().close();
+        time::timeout(TIMEOUT, socket.run())
+            .await
+            .expect("Socket did not close")
+            .unwrap();
+    }
+}
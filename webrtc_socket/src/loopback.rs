@@ -0,0 +1,408 @@
+//! In-process stand-in for the WebRTC connections, so GGRS sessions and
+//! game logic can be tested without a matchmaker, ICE or timing.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::sync::{
+    mpsc::{self, Receiver, Sender},
+    watch,
+};
+use uuid::Uuid;
+
+use crate::{
+    broadcast::{Broadcast, Groups, Outgoing, Recipients},
+    frame::{Inbox, MessageKind, PacketReceiver},
+    message::StateMessage,
+    peer::{Channel, QueueLimits},
+    wire::DecodeErrors,
+    AppSocket, GgrsSocket, Packet, Payload, PeerStats,
+};
+
+/// The network's end of a [`LoopbackSocket`].
+#[derive(Debug)]
+struct Endpoint {
+    inbox: Inbox,
+    out_data_rx: Receiver<Outgoing>,
+    groups: Groups,
+    state_rx: Receiver<StateMessage>,
+    ready_peers_tx: watch::Sender<Vec<Uuid>>,
+    stats: HashMap<Uuid, PeerStats>,
+    stats_tx: watch::Sender<HashMap<Uuid, PeerStats>>,
+}
+
+/// Virtual peers that are all connected to each other. Nothing arrives until
+/// [`LoopbackNetwork::deliver`] is called, so tests decide when packets move.
+#[derive(Debug, Default)]
+pub struct LoopbackNetwork {
+    // Ordered, so packets are delivered in the same order on every run
+    endpoints: BTreeMap<Uuid, Endpoint>,
+}
+
+impl LoopbackNetwork {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Network of `n` peers with the ids `1..=n`.
+    pub fn with_peers(n: u128) -> (Self, Vec<LoopbackSocket>) {
+        let mut network = Self::new();
+        let sockets = (1..=n)
+            .map(|id| network.add_peer(Uuid::from_u128(id)))
+            .collect();
+        (network, sockets)
+    }
+
+    /// Adds a peer that is connected to all others right away.
+    pub fn add_peer(&mut self, id: Uuid) -> LoopbackSocket {
+        let limits = QueueLimits::default();
+        let (inbox, in_data_rx, in_ggrs_rx, in_app_rx) = Inbox::new(limits.incoming);
+        let (out_data_tx, out_data_rx) = mpsc::channel(limits.outgoing);
+        let groups = Groups::default();
+        let (state_tx, state_rx) = mpsc::channel(limits.control);
+        let (ready_peers_tx, ready_peers) = watch::channel(vec![]);
+        let (stats_tx, stats) = watch::channel(HashMap::new());
+        let decode_errors = inbox.errors().clone();
+        self.endpoints.insert(
+            id,
+            Endpoint {
+                inbox,
+                out_data_rx,
+                groups: groups.clone(),
+                state_rx,
+                ready_peers_tx,
+                stats: HashMap::new(),
+                stats_tx,
+            },
+        );
+        self.publish_ready_peers();
+        LoopbackSocket {
+            id,
+            in_data_rx,
+            in_ggrs_rx: Some(in_ggrs_rx),
+            in_app_rx: Some(in_app_rx),
+            out_data_tx,
+            groups,
+            state_tx,
+            ready_peers,
+            decode_errors,
+            stats,
+        }
+    }
+
+    /// Disconnects the peer, packets for it are dropped from now on.
+    pub fn remove_peer(&mut self, id: Uuid) {
+        if self.endpoints.remove(&id).is_some() {
+            self.publish_ready_peers();
+        }
+    }
+
+    pub fn peers(&self) -> Vec<Uuid> {
+        self.endpoints.keys().copied().collect()
+    }
+
+    /// Moves every packet sent so far to its receiver, answers pending
+    /// [`GgrsSocket::players`] requests and updates the stats of every peer.
+    /// Returns the number of delivered packets.
+    pub fn deliver(&mut self) -> usize {
+        let ids = self.peers();
+        let mut packets = vec![];
+        for (&from, endpoint) in self.endpoints.iter_mut() {
+            while let Ok(outgoing) = endpoint.out_data_rx.try_recv() {
+                match outgoing {
+                    Outgoing::Packet(packet) => packets.push((from, packet)),
+                    Outgoing::Broadcast(broadcast) => {
+                        let others = ids.iter().filter(|&&id| id != from).copied();
+                        for to in broadcast.to.select(others, &endpoint.groups) {
+                            let payload = broadcast.payload.clone();
+                            let packet =
+                                Packet::with_kind(to, broadcast.channel, broadcast.kind, payload);
+                            packets.push((from, packet));
+                        }
+                    }
+                }
+            }
+        }
+        let mut delivered = 0;
+        for (from, packet) in packets {
+            let (to, bytes) = (packet.id, packet.payload.len());
+            let sent = match self.endpoints.get_mut(&to) {
+                Some(endpoint) => {
+                    let stats = endpoint.stats.entry(from).or_default();
+                    stats.packets_received += 1;
+                    stats.bytes_received += bytes as u64;
+                    let packet = Packet { id: from, ..packet }.received();
+                    if endpoint.inbox.deliver(packet) {
+                        delivered += 1;
+                    } else {
+                        stats.dropped_incoming += 1;
+                    }
+                    true
+                }
+                None => false,
+            };
+            let stats = self
+                .endpoints
+                .get_mut(&from)
+                .unwrap()
+                .stats
+                .entry(to)
+                .or_default();
+            if sent {
+                stats.packets_sent += 1;
+                stats.bytes_sent += bytes as u64;
+            } else {
+                stats.dropped += 1;
+            }
+        }
+        for endpoint in self.endpoints.values() {
+            endpoint.stats_tx.send_replace(endpoint.stats.clone());
+        }
+
+        for (id, endpoint) in self.endpoints.iter_mut() {
+            while let Ok(msg) = endpoint.state_rx.try_recv() {
+                if let StateMessage::ReadyPeers(tx) = msg {
+                    let _ = tx.send(ids.iter().filter(|&other| other != id).copied().collect());
+                }
+            }
+        }
+        delivered
+    }
+
+    fn publish_ready_peers(&self) {
+        for (id, endpoint) in self.endpoints.iter() {
+            let others = self.endpoints.keys().filter(|&other| other != id);
+            endpoint
+                .ready_peers_tx
+                .send_replace(others.copied().collect());
+        }
+    }
+}
+
+/// A virtual peer of a [`LoopbackNetwork`], with the same channels a
+/// [`crate::WebRTCSocket`] has.
+#[derive(Debug)]
+pub struct LoopbackSocket {
+    id: Uuid,
+    in_data_rx: PacketReceiver,
+    in_ggrs_rx: Option<PacketReceiver>,
+    in_app_rx: Option<PacketReceiver>,
+    out_data_tx: Sender<Outgoing>,
+    groups: Groups,
+    state_tx: Sender<StateMessage>,
+    ready_peers: watch::Receiver<Vec<Uuid>>,
+    decode_errors: Arc<DecodeErrors>,
+    stats: watch::Receiver<HashMap<Uuid, PeerStats>>,
+}
+
+impl LoopbackSocket {
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    /// Handle for GGRS, only available once.
+    pub fn ggrs_socket(&mut self) -> Option<GgrsSocket> {
+        Some(GgrsSocket::from_channels(
+            self.id,
+            self.in_ggrs_rx.take()?,
+            self.out_data_tx.clone(),
+            self.state_tx.clone(),
+            self.ready_peers.clone(),
+            self.decode_errors.clone(),
+            self.stats.clone(),
+        ))
+    }
+
+    /// Typed application messages, only available once.
+    pub fn app_socket<T>(&mut self) -> Option<AppSocket<T>>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        Some(AppSocket::from_channels(
+            self.in_app_rx.take()?,
+            self.out_data_tx.clone(),
+            self.decode_errors.clone(),
+        ))
+    }
+
+    /// Sends raw bytes like [`crate::WebRTCSocket::send_data`].
+    pub fn send(&mut self, id: Uuid, channel: Channel, payload: Payload) {
+        let packet = Packet::new(id, channel, payload);
+        let _ = self.out_data_tx.try_send(packet.into());
+    }
+
+    /// Sends raw bytes to several peers like
+    /// [`crate::WebRTCSocket::broadcast`].
+    pub fn broadcast(&mut self, to: Recipients, channel: Channel, payload: Payload) {
+        let broadcast = Broadcast {
+            to,
+            channel,
+            kind: MessageKind::Raw,
+            payload,
+        };
+        let _ = self.out_data_tx.try_send(broadcast.into());
+    }
+
+    /// Named groups of peers for [`Recipients::Group`].
+    pub fn groups(&self) -> Groups {
+        self.groups.clone()
+    }
+
+    /// Raw bytes received since the last call with their sender.
+    pub fn receive(&mut self) -> Vec<(Uuid, Payload)> {
+        std::iter::from_fn(|| self.in_data_rx.try_recv().ok())
+            .map(|packet| (packet.id, packet.payload))
+            .collect()
+    }
+
+    pub fn decode_errors(&self) -> &DecodeErrors {
+        &self.decode_errors
+    }
+
+    /// Traffic of every peer as of the last delivery.
+    pub fn peer_stats(&self) -> HashMap<Uuid, PeerStats> {
+        self.stats.borrow().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ggrs::{PlayerType, SessionBuilder, SessionState};
+    use uuid::Uuid;
+
+    use super::LoopbackNetwork;
+    use crate::{peer::Channel, Payload, Recipients};
+
+    struct TestConfig;
+
+    impl ggrs::Config for TestConfig {
+        type Input = u8;
+        type State = u8;
+        type Address = Uuid;
+    }
+
+    #[test]
+    fn raw_packets_arrive_after_deliver() {
+        let (mut network, mut sockets) = LoopbackNetwork::with_peers(3);
+        let (a, b) = (sockets[0].id(), sockets[1].id());
+        sockets[0].send(b, Channel::Reliable, Payload::from_static(b"hello"));
+        sockets[0].send(Uuid::from_u128(42), Channel::Reliable, Payload::new());
+        assert!(sockets[1].receive().is_empty());
+
+        assert_eq!(network.deliver(), 1);
+        assert_eq!(
+            sockets[1].receive(),
+            vec![(a, Payload::from_static(b"hello"))]
+        );
+        assert!(sockets[2].receive().is_empty());
+
+        let stats = &sockets[0].peer_stats()[&b];
+        assert_eq!((stats.packets_sent, stats.bytes_sent), (1, 5));
+        assert_eq!(sockets[0].peer_stats()[&Uuid::from_u128(42)].dropped, 1);
+        assert_eq!(sockets[1].peer_stats()[&a].bytes_received, 5);
+    }
+
+    #[test]
+    fn broadcasts_reach_the_selected_peers() {
+        let (mut network, mut sockets) = LoopbackNetwork::with_peers(4);
+        let ids: Vec<_> = sockets.iter().map(|socket| socket.id()).collect();
+        let hello = Payload::from_static(b"hello");
+
+        sockets[0].broadcast(Recipients::All, Channel::Reliable, hello.clone());
+        assert_eq!(network.deliver(), 3);
+        for socket in sockets[1..].iter_mut() {
+            assert_eq!(socket.receive(), vec![(ids[0], hello.clone())]);
+        }
+
+        let except = Recipients::AllExcept(ids[2]);
+        sockets[0].broadcast(except, Channel::Reliable, hello.clone());
+        assert_eq!(network.deliver(), 2);
+        assert!(sockets[2].receive().is_empty());
+        assert_eq!(sockets[3].receive().len(), 1);
+
+        let groups = sockets[0].groups();
+        groups.join("red", ids[1]);
+        groups.join("red", ids[3]);
+        groups.join("blue", ids[2]);
+        assert_eq!(groups.members("red"), vec![ids[1], ids[3]]);
+        let red = Recipients::Group("red".to_owned());
+        sockets[0].broadcast(red.clone(), Channel::Reliable, hello.clone());
+        assert_eq!(network.deliver(), 2);
+        assert_eq!(sockets[1].receive().len(), 1);
+        assert!(sockets[2].receive().is_empty());
+        assert_eq!(sockets[3].receive().len(), 1);
+
+        // groups are local to the socket
+        sockets[1].broadcast(red.clone(), Channel::Reliable, hello.clone());
+        assert_eq!(network.deliver(), 0);
+
+        groups.leave_all(ids[3]);
+        groups.leave("red", ids[1]);
+        assert!(groups.members("red").is_empty());
+        sockets[0].broadcast(red, Channel::Reliable, hello);
+        assert_eq!(network.deliver(), 0);
+    }
+
+    #[test]
+    fn app_messages_are_typed() {
+        let (mut network, mut sockets) = LoopbackNetwork::with_peers(2);
+        let mut a = sockets[0].app_socket::<String>().unwrap();
+        let mut b = sockets[1].app_socket::<String>().unwrap();
+        assert!(sockets[0].app_socket::<String>().is_none());
+
+        a.send(sockets[1].id(), Channel::Reliable, &"ready".to_owned())
+            .unwrap();
+        network.deliver();
+        assert_eq!(b.receive(), vec![(sockets[0].id(), "ready".to_owned())]);
+
+        a.broadcast(Recipients::All, Channel::Reliable, &"go".to_owned())
+            .unwrap();
+        network.deliver();
+        assert_eq!(b.receive(), vec![(sockets[0].id(), "go".to_owned())]);
+    }
+
+    #[tokio::test]
+    async fn players_include_every_peer() {
+        let (mut network, mut sockets) = LoopbackNetwork::with_peers(3);
+        let ggrs = sockets[1].ggrs_socket().unwrap();
+        let expected = vec![
+            PlayerType::Remote(Uuid::from_u128(1)),
+            PlayerType::Local,
+            PlayerType::Remote(Uuid::from_u128(3)),
+        ];
+        assert_eq!(ggrs.players_snapshot(), expected);
+
+        // the request is answered by the next delivery
+        let (players, _) = tokio::join!(ggrs.players(), async { network.deliver() });
+        assert_eq!(players.unwrap(), expected);
+    }
+
+    #[test]
+    fn ggrs_sessions_synchronize() {
+        let (mut network, mut sockets) = LoopbackNetwork::with_peers(2);
+        let mut sessions: Vec<_> = sockets
+            .iter_mut()
+            .map(|socket| {
+                let ggrs = socket.ggrs_socket().unwrap();
+                let mut builder = SessionBuilder::<TestConfig>::new().with_num_players(2);
+                for (handle, player) in ggrs.players_snapshot().into_iter().enumerate() {
+                    builder = builder.add_player(player, handle).unwrap();
+                }
+                builder.start_p2p_session(ggrs).unwrap()
+            })
+            .collect();
+
+        for _ in 0..100 {
+            for session in sessions.iter_mut() {
+                session.poll_remote_clients();
+            }
+            network.deliver();
+        }
+        for session in sessions.iter() {
+            assert_eq!(session.current_state(), SessionState::Running);
+        }
+    }
+}
@@ -0,0 +1,162 @@
+//! Wire protocol of [matchbox_server](https://github.com/johanhelsing/matchbox),
+//! spoken by [`crate::signaller::MatchboxSignaller`] and the matchmaker's
+//! matchbox route.
+
+use uuid::Uuid;
+use webrtc::{
+    ice_transport::ice_candidate::RTCIceCandidateInit,
+    peer_connection::sdp::{sdp_type::RTCSdpType, session_description::RTCSessionDescription},
+};
+
+use crate::{error::Result, message::Message};
+
+/// Sent by clients.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum PeerRequest {
+    Signal {
+        receiver: Uuid,
+        data: PeerSignal,
+    },
+    /// Keeps proxies from closing an idle connection, the server ignores it.
+    KeepAlive,
+}
+
+/// Sent by the server.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum PeerEvent {
+    /// Always the first event, there is no session to resume.
+    IdAssigned(Uuid),
+    /// Peers already in the room are told about newcomers and send the offer.
+    NewPeer(Uuid),
+    PeerLeft(Uuid),
+    Signal {
+        sender: Uuid,
+        data: PeerSignal,
+    },
+}
+
+/// Offers and answers as plain SDP, candidates as the JSON of their init.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum PeerSignal {
+    IceCandidate(String),
+    Offer(String),
+    Answer(String),
+}
+
+impl PeerSignal {
+    /// Signal and sender of an offer, answer or candidate, `None` for messages
+    /// matchbox has no equivalent for.
+    pub fn from_message(msg: Message) -> Result<Option<(Uuid, PeerSignal)>> {
+        Ok(match msg {
+            Message::Offer { id, offer } => Some((id, PeerSignal::Offer(offer.sdp))),
+            Message::Answer { id, answer } => Some((id, PeerSignal::Answer(answer.sdp))),
+            Message::IceCandidate { id, candidate } => {
+                let candidate = serde_json::to_string(&candidate)?;
+                Some((id, PeerSignal::IceCandidate(candidate)))
+            }
+            _ => None,
+        })
+    }
+
+    /// The [`Message`] `sender` would have sent with the matchmaker protocol.
+    pub fn into_message(self, sender: Uuid) -> Result<Message> {
+        let id = sender;
+        Ok(match self {
+            PeerSignal::Offer(sdp) => Message::Offer {
+                id,
+                offer: description(RTCSdpType::Offer, sdp)?,
+            },
+            PeerSignal::Answer(sdp) => Message::Answer {
+                id,
+                answer: description(RTCSdpType::Answer, sdp)?,
+            },
+            PeerSignal::IceCandidate(candidate) => Message::IceCandidate {
+                id,
+                candidate: serde_json::from_str::<RTCIceCandidateInit>(&candidate)?,
+            },
+        })
+    }
+}
+
+/// Session descriptions are only public through serde, the sdp is parsed once
+/// it is applied to the connection.
+fn description(sdp_type: RTCSdpType, sdp: String) -> Result<RTCSessionDescription> {
+    let json = serde_json::json!({ "type": sdp_type, "sdp": sdp });
+    Ok(serde_json::from_value(json)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::{PeerEvent, PeerRequest, PeerSignal};
+    use crate::message::Message;
+
+    /// Recorded from a matchbox_socket client talking to matchbox_server.
+    const TRANSCRIPT: &str = include_str!("../tests/transcripts/matchbox.txt");
+
+    fn id(name: &str) -> Uuid {
+        match name {
+            "$A" => Uuid::from_u128(0xa),
+            "$B" => Uuid::from_u128(0xb),
+            name => panic!("Unknown peer {name}"),
+        }
+    }
+
+    /// Lines of the transcript with the peer placeholders replaced by ids.
+    fn lines() -> Vec<(String, String)> {
+        TRANSCRIPT
+            .lines()
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let (direction, json) = line.split_once(' ').unwrap();
+                let json = json
+                    .replace("$A", &id("$A").to_string())
+                    .replace("$B", &id("$B").to_string());
+                (direction.to_owned(), json)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn transcript_round_trips() {
+        for (direction, json) in lines() {
+            if json == "close" {
+                continue;
+            }
+            let encoded = if direction.ends_with('>') {
+                serde_json::to_string(&serde_json::from_str::<PeerRequest>(&json).unwrap())
+            } else {
+                serde_json::to_string(&serde_json::from_str::<PeerEvent>(&json).unwrap())
+            };
+            assert_eq!(encoded.unwrap(), json);
+        }
+    }
+
+    #[test]
+    fn signals_convert_to_messages_and_back() {
+        let signals = lines().into_iter().filter_map(|(_, json)| {
+            match serde_json::from_str::<PeerEvent>(&json) {
+                Ok(PeerEvent::Signal { sender, data }) => Some((sender, data)),
+                _ => None,
+            }
+        });
+        let mut count = 0;
+        for (sender, data) in signals {
+            let msg = data.clone().into_message(sender).unwrap();
+            assert!(matches!(
+                msg,
+                Message::Offer { .. } | Message::Answer { .. } | Message::IceCandidate { .. }
+            ));
+            assert_eq!(PeerSignal::from_message(msg).unwrap(), Some((sender, data)));
+            count += 1;
+        }
+        assert!(count >= 3);
+    }
+
+    #[test]
+    fn end_of_candidates_has_no_signal() {
+        let msg = Message::EndOfCandidates { id: id("$A") };
+        assert_eq!(PeerSignal::from_message(msg).unwrap(), None);
+    }
+}
@@ -1,10 +1,21 @@
-use tokio::sync::mpsc::UnboundedSender;
+use std::net::SocketAddr;
+
+use tokio::sync::oneshot;
 use uuid::Uuid;
-use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::{
+    ice_transport::ice_candidate::RTCIceCandidateInit,
+    peer_connection::sdp::session_description::RTCSessionDescription,
+};
+
+use crate::peer::Channel;
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub enum Message {
     Id(Uuid),
+    /// Peers currently connected to the server, sent after every login.
+    Peers {
+        ids: Vec<Uuid>,
+    },
     NewPeer {
         id: Uuid,
     },
@@ -19,13 +30,32 @@ pub enum Message {
         id: Uuid,
         answer: RTCSessionDescription,
     },
+    /// A trickled candidate including its `sdpMid` and `sdpMLineIndex`.
     IceCandidate {
         id: Uuid,
-        candidate: String,
+        candidate: RTCIceCandidateInit,
     },
+    /// The sender gathered all of its candidates.
+    EndOfCandidates {
+        id: Uuid,
+    },
+    /// Where the sender receives GGRS packets over plain UDP, see
+    /// [`crate::udp`]. The matchmaker fills in an unspecified IP with the one
+    /// the sender connected from.
+    UdpAddress {
+        id: Uuid,
+        addr: SocketAddr,
+    },
+}
+
+impl Message {
+    /// Decodes a message received from the signaling server.
+    pub fn from_json(json: &[u8]) -> serde_json::Result<Self> {
+        serde_json::from_slice(json)
+    }
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct PeerMessage {
     pub peer_id: Uuid,
     pub content: Message,
@@ -33,5 +63,14 @@ pub struct PeerMessage {
 
 #[derive(Debug)]
 pub enum StateMessage {
-    ReadyPeers(UnboundedSender<Vec<Uuid>>),
+    /// Sorted ids of the peers that are ready.
+    ReadyPeers(oneshot::Sender<Vec<Uuid>>),
+    /// Opens another data channel to an established peer and renegotiates the
+    /// connection, the peer opens the channel in return. Peers that connect
+    /// later only get [`Channel::ALL`].
+    OpenChannel {
+        id: Uuid,
+        channel: Channel,
+    },
+    Close,
 }
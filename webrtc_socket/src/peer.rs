@@ -1,31 +1,75 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 use getset::Getters;
 use tokio::sync::mpsc;
-use tracing::{debug, info, warn};
+use tracing::{debug, info, trace, warn};
 use uuid::Uuid;
 use webrtc::{
     api::{
         interceptor_registry::register_default_interceptors, media_engine::MediaEngine, APIBuilder,
     },
-    data_channel::{
-        data_channel_init::RTCDataChannelInit, data_channel_message::DataChannelMessage,
-        RTCDataChannel,
-    },
+    data_channel::{data_channel_message::DataChannelMessage, RTCDataChannel},
+    ice_transport::ice_candidate::RTCIceCandidateInit,
     interceptor::registry::Registry,
     peer_connection::{
-        configuration::RTCConfiguration, peer_connection_state::RTCPeerConnectionState,
-        sdp::session_description::RTCSessionDescription, RTCPeerConnection,
+        configuration::RTCConfiguration,
+        offer_answer_options::RTCOfferOptions,
+        peer_connection_state::RTCPeerConnectionState,
+        sdp::{sdp_type::RTCSdpType, session_description::RTCSessionDescription},
+        signaling_state::RTCSignalingState,
+        RTCPeerConnection,
     },
+    stats::StatsReportType,
 };
 
 use crate::{
+    error::{Error, Result},
+    frame::{self, Inbox, MessageKind},
     message::{Message, PeerMessage},
+    stats::{CandidateType, PeerCounters, PeerStats},
+    wire::DecodeSource,
     Packet, Payload,
 };
 
+mod channel;
 mod rtc_config;
-pub use rtc_config::{RtcConfig, RtcConfigBuilder};
+mod state;
+pub use channel::Channel;
+pub use rtc_config::{Heartbeat, QueueLimits, Reconnect, RtcConfig, RtcConfigBuilder};
+pub use state::PeerState;
+
+/// How often we try to restore a lost connection before giving up on a peer.
+pub const MAX_ICE_RESTARTS: u32 = 3;
+
+/// How often a running socket checks whether an ICE restart timed out, see
+/// [`RtcConfig::ice_restart_timeout`].
+pub const RESTART_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Changes a peer reports from its webrtc callbacks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerUpdate {
+    State(PeerState),
+    ChannelOpen(Channel),
+    /// An outgoing data channel buffers less than
+    /// [`QueueLimits::buffered_low`] again.
+    Drained(Channel),
+}
+
+/// What to do with an offer from a peer, see [`Peer::handle_offer`].
+#[derive(Debug)]
+pub enum OfferResponse {
+    /// Send this answer back.
+    Answer(PeerMessage),
+    /// Both sides offered at once and ours wins, the remote peer will answer it.
+    Ignored,
+    /// Both sides offered at once during the first handshake and theirs wins.
+    /// Replace this peer with a fresh one and accept the offer there.
+    Reset,
+}
 
 #[derive(Getters)]
 pub struct Peer {
@@ -34,9 +78,33 @@ pub struct Peer {
     #[getset(get = "pub")]
     connection: Arc<RTCPeerConnection>,
     ws_tx: mpsc::UnboundedSender<PeerMessage>,
-    outgoing_data_channel: Arc<RTCDataChannel>,
-    incoming_data_tx: mpsc::UnboundedSender<Packet>,
-    ready: Arc<Mutex<bool>>,
+    updates_tx: mpsc::UnboundedSender<(Uuid, PeerUpdate)>,
+    outgoing_data_channels: HashMap<Channel, Arc<RTCDataChannel>>,
+    inbox: Inbox,
+    open_channels: Arc<Mutex<HashSet<Channel>>>,
+    state: Arc<Mutex<PeerState>>,
+    /// Remote candidates that arrived before the remote description.
+    pending_candidates: Mutex<Vec<RTCIceCandidateInit>>,
+    /// ICE restarts since the peer was last connected.
+    restarts: u32,
+    /// When the connection was lost or last restarted, `None` while connected.
+    lost_at: Option<Instant>,
+    /// Whether the socket announced this peer as ready since it last connected.
+    announced_ready: bool,
+    /// Whether the peer was ever connected, before that silence only means
+    /// that it is still connecting.
+    established: bool,
+    /// When we last heard from the peer, see [`Heartbeat`].
+    last_seen: Arc<Mutex<Instant>>,
+    unresponsive: bool,
+    /// Heartbeats carry their send time relative to this.
+    epoch: Instant,
+    /// Measured when a heartbeat comes back, see [`MessageKind::HeartbeatAck`].
+    rtt: Arc<Mutex<Option<Duration>>>,
+    counters: Arc<PeerCounters>,
+    limits: QueueLimits,
+    /// Frames waiting for [`Peer::flush`], by channel.
+    outgoing: HashMap<Channel, VecDeque<Payload>>,
 }
 
 impl std::fmt::Debug for Peer {
@@ -47,32 +115,74 @@ impl std::fmt::Debug for Peer {
     }
 }
 
+impl Drop for Peer {
+    fn drop(&mut self) {
+        // Closing is async, so hand it to the runtime if there is one
+        let connection = self.connection.clone();
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            handle.spawn(async move {
+                let _ = connection.close().await;
+            });
+        }
+    }
+}
+
 impl Peer {
     pub async fn new(
         id: Uuid,
         peer_id: Uuid,
         config: &RtcConfig,
         ws_tx: mpsc::UnboundedSender<PeerMessage>,
-        incoming_data_tx: mpsc::UnboundedSender<Packet>,
-    ) -> anyhow::Result<Self> {
+        inbox: Inbox,
+        updates_tx: mpsc::UnboundedSender<(Uuid, PeerUpdate)>,
+    ) -> Result<Self> {
         let connection = Self::create_peer_connection(config).await?;
-        let ready = Arc::new(Mutex::new(false));
-        let outgoing_data_channel = Self::create_data_channel(&connection).await?;
+        let open_channels = Arc::new(Mutex::new(HashSet::new()));
+        let mut outgoing_data_channels = HashMap::new();
+        for channel in Channel::ALL {
+            let data_channel = Self::create_data_channel(
+                &connection,
+                channel,
+                peer_id,
+                &config.queue_limits,
+                updates_tx.clone(),
+            )
+            .await?;
+            outgoing_data_channels.insert(channel, data_channel);
+        }
         let peer = Self {
             id,
             peer_id,
             connection,
             ws_tx,
-            outgoing_data_channel,
-            incoming_data_tx,
-            ready,
+            updates_tx: updates_tx.clone(),
+            outgoing_data_channels,
+            inbox,
+            open_channels,
+            state: Arc::new(Mutex::new(PeerState::New)),
+            pending_candidates: Default::default(),
+            restarts: 0,
+            lost_at: None,
+            announced_ready: false,
+            established: false,
+            last_seen: Arc::new(Mutex::new(Instant::now())),
+            unresponsive: false,
+            epoch: Instant::now(),
+            rtt: Default::default(),
+            counters: Default::default(),
+            limits: config.queue_limits.clone(),
+            outgoing: Default::default(),
         };
+        for data_channel in peer.outgoing_data_channels.values() {
+            peer.receive_acks(data_channel).await;
+        }
+        peer.track_state(updates_tx.clone()).await;
         peer.ice_candidates().await?;
-        peer.connect_incoming_data_channel().await?;
+        peer.connect_incoming_data_channel(updates_tx).await?;
         Ok(peer)
     }
 
-    async fn create_peer_connection(config: &RtcConfig) -> anyhow::Result<Arc<RTCPeerConnection>> {
+    async fn create_peer_connection(config: &RtcConfig) -> Result<Arc<RTCPeerConnection>> {
         let mut m = MediaEngine::default();
         m.register_default_codecs()?;
         let mut registry = Registry::new();
@@ -87,22 +197,24 @@ impl Peer {
             ..Default::default()
         };
         let connection = Arc::new(api.new_peer_connection(config).await?);
+        Ok(connection)
+    }
 
-        connection
+    async fn track_state(&self, updates_tx: mpsc::UnboundedSender<(Uuid, PeerUpdate)>) {
+        let peer_id = self.peer_id;
+        let state = self.state.clone();
+        self.connection
             .on_peer_connection_state_change(Box::new(move |s: RTCPeerConnectionState| {
-                info!("Peer Connection State has changed: {}", s);
-
-                if s == RTCPeerConnectionState::Failed {
-                    info!("Peer Connection has gone to failed state");
-                }
-
+                info!("Peer Connection State with {peer_id} has changed: {}", s);
+                let s = PeerState::from(s);
+                *state.lock().unwrap() = s;
+                let _ = updates_tx.send((peer_id, PeerUpdate::State(s)));
                 Box::pin(async {})
             }))
             .await;
-        Ok(connection)
     }
 
-    async fn ice_candidates(&self) -> anyhow::Result<()> {
+    async fn ice_candidates(&self) -> Result<()> {
         let id = self.id;
         let peer_id = self.peer_id;
         let tx = self.ws_tx.clone();
@@ -110,15 +222,20 @@ impl Peer {
             .on_ice_candidate(Box::new(move |c| {
                 let tx2 = tx.clone();
                 Box::pin(async move {
-                    if let Some(candidate) = c {
-                        let candidate = candidate.to_json().await.unwrap().candidate;
-                        debug!(?candidate);
-                        let msg = PeerMessage {
-                            peer_id,
-                            content: Message::IceCandidate { id, candidate },
-                        };
-                        tx2.send(msg).unwrap();
-                    }
+                    let content = match c {
+                        Some(candidate) => match candidate.to_json().await {
+                            Ok(candidate) => {
+                                debug!(?candidate);
+                                Message::IceCandidate { id, candidate }
+                            }
+                            Err(e) => {
+                                warn!("Failed to encode ice candidate: {e}");
+                                return;
+                            }
+                        },
+                        None => Message::EndOfCandidates { id },
+                    };
+                    let _ = tx2.send(PeerMessage { peer_id, content });
                 })
             }))
             .await;
@@ -127,28 +244,18 @@ impl Peer {
 
     async fn create_data_channel(
         connection: &RTCPeerConnection,
-    ) -> anyhow::Result<Arc<RTCDataChannel>> {
-        let config = RTCDataChannelInit {
-            ordered: Some(false),
-            max_retransmits: Some(0),
-            ..Default::default()
-        };
-
-        let data_channel = connection.create_data_channel("data", Some(config)).await?;
+        channel: Channel,
+        peer_id: Uuid,
+        limits: &QueueLimits,
+        updates_tx: mpsc::UnboundedSender<(Uuid, PeerUpdate)>,
+    ) -> Result<Arc<RTCDataChannel>> {
+        let data_channel = connection
+            .create_data_channel(&channel.label(), Some(channel.init()))
+            .await?;
         data_channel
             .on_open(Box::new(move || Box::pin(async move {})))
             .await;
 
-        // Register text message handling
-        let d_label = data_channel.label().to_owned();
-        data_channel
-            .on_message(Box::new(move |msg: DataChannelMessage| {
-                let msg_str = String::from_utf8(msg.data.to_vec()).unwrap();
-                info!("Message from DataChannel '{}': '{}'", d_label, msg_str);
-                Box::pin(async {})
-            }))
-            .await;
-
         data_channel
             .on_close(Box::new(move || {
                 debug!("Data channel closed");
@@ -163,37 +270,119 @@ impl Peer {
             }))
             .await;
 
+        data_channel
+            .set_buffered_amount_low_threshold(limits.buffered_low)
+            .await;
         data_channel
             .on_buffered_amount_low(Box::new(move || {
-                info!("Buffered amound low");
+                trace!("Data channel '{}' to {peer_id} drained", channel.label());
+                let _ = updates_tx.send((peer_id, PeerUpdate::Drained(channel)));
                 Box::pin(async move {})
             }))
             .await;
         Ok(data_channel)
     }
 
-    async fn connect_incoming_data_channel(&self) -> anyhow::Result<()> {
-        let tx = self.incoming_data_tx.clone();
+    /// Peers send on their own channels, only our heartbeats come back on
+    /// them, see [`MessageKind::HeartbeatAck`].
+    async fn receive_acks(&self, data_channel: &RTCDataChannel) {
+        let label = data_channel.label().to_owned();
+        let epoch = self.epoch;
+        let rtt = self.rtt.clone();
+        let counters = self.counters.clone();
+        let last_seen = self.last_seen.clone();
+        data_channel
+            .on_message(Box::new(move |msg: DataChannelMessage| {
+                let len = msg.data.len();
+                match frame::decode(msg.data) {
+                    Some((MessageKind::HeartbeatAck, payload)) => {
+                        counters.received(len);
+                        *last_seen.lock().unwrap() = Instant::now();
+                        if let Some(sent) = Self::heartbeat_time(&payload) {
+                            *rtt.lock().unwrap() = epoch.elapsed().checked_sub(sent);
+                        }
+                    }
+                    _ => info!("Ignoring {len} bytes received on outgoing DataChannel '{label}'"),
+                }
+                Box::pin(async {})
+            }))
+            .await;
+    }
+
+    async fn connect_incoming_data_channel(
+        &self,
+        updates_tx: mpsc::UnboundedSender<(Uuid, PeerUpdate)>,
+    ) -> Result<()> {
+        let inbox = self.inbox.clone();
         let id = self.peer_id;
-        let ready = self.ready.clone();
+        let open_channels = self.open_channels.clone();
+        let counters = self.counters.clone();
+        let last_seen = self.last_seen.clone();
         self.connection
-            .on_data_channel(Box::new(move |channel| {
-                let tx2 = tx.clone();
-                let ready2 = ready.clone();
+            .on_data_channel(Box::new(move |data_channel| {
+                let inbox2 = inbox.clone();
+                let counters2 = counters.clone();
+                let last_seen2 = last_seen.clone();
+                let last_seen3 = last_seen.clone();
+                let open_channels2 = open_channels.clone();
+                let updates_tx2 = updates_tx.clone();
                 Box::pin(async move {
-                    channel
+                    let channel = match Channel::from_label(data_channel.label()) {
+                        Some(channel) => channel,
+                        None => {
+                            warn!("Ignoring unknown data channel '{}'", data_channel.label());
+                            return;
+                        }
+                    };
+                    data_channel
                         .on_open(Box::new(move || {
+                            *last_seen3.lock().unwrap() = Instant::now();
                             Box::pin(async move {
-                                *ready2.lock().unwrap() = true;
+                                open_channels2.lock().unwrap().insert(channel);
+                                let _ = updates_tx2.send((id, PeerUpdate::ChannelOpen(channel)));
                             })
                         }))
                         .await;
-                    channel
+                    // Answers heartbeats without holding on to the channel
+                    let echo_channel = Arc::downgrade(&data_channel);
+                    data_channel
                         .on_message(Box::new(move |msg: DataChannelMessage| {
-                            let payload: Payload = msg.data;
-                            let packet = Packet { id, payload };
-                            let _ = tx2.send(packet);
-                            Box::pin(async {})
+                            let len = msg.data.len();
+                            let mut ack = None;
+                            match frame::decode(msg.data) {
+                                Some((MessageKind::Heartbeat, payload)) => {
+                                    counters2.received(len);
+                                    *last_seen2.lock().unwrap() = Instant::now();
+                                    ack = Some(frame::encode(MessageKind::HeartbeatAck, &payload));
+                                }
+                                Some((kind, payload)) => {
+                                    counters2.received(len);
+                                    *last_seen2.lock().unwrap() = Instant::now();
+                                    let packet =
+                                        Packet::with_kind(id, channel, kind, payload).received();
+                                    if !inbox2.deliver(packet) {
+                                        counters2.dropped_incoming();
+                                        debug!("Dropping packet from {id}, receiver is full");
+                                    }
+                                }
+                                None => {
+                                    counters2.undecodable();
+                                    inbox2.errors().record(DecodeSource::Frame);
+                                    warn!("Dropping unframed packet from {id}");
+                                }
+                            }
+                            let echo_channel = echo_channel.clone();
+                            let counters3 = counters2.clone();
+                            Box::pin(async move {
+                                if let (Some(ack), Some(data_channel)) =
+                                    (ack, echo_channel.upgrade())
+                                {
+                                    match data_channel.send(&ack).await {
+                                        Ok(_) => counters3.sent(ack.len()),
+                                        Err(e) => debug!("Failed to answer heartbeat: {e}"),
+                                    }
+                                }
+                            })
                         }))
                         .await;
                 })
@@ -203,11 +392,152 @@ impl Peer {
         Ok(())
     }
 
+    /// A peer is ready while it is connected, all of its incoming data
+    /// channels are open and it answers heartbeats.
     pub async fn ready(&self) -> bool {
-        *self.ready.lock().unwrap()
+        self.connected() && !self.unresponsive
     }
 
-    pub async fn handshake_offer(&self) -> anyhow::Result<PeerMessage> {
+    pub(crate) fn connected(&self) -> bool {
+        let open_channels = self.open_channels.lock().unwrap();
+        self.state() == PeerState::Connected
+            && Channel::ALL
+                .iter()
+                .all(|channel| open_channels.contains(channel))
+    }
+
+    /// Whether we opened `channel` to send on it.
+    pub(crate) fn sends_on(&self, channel: Channel) -> bool {
+        self.outgoing_data_channels.contains_key(&channel)
+    }
+
+    /// Opens another outgoing data channel on the established connection,
+    /// returns `false` if it is already open. The remote peer opens the same
+    /// channel once ours arrives, so both sides can send on it.
+    pub(crate) async fn open_channel(&mut self, channel: Channel) -> Result<bool> {
+        if self.sends_on(channel) {
+            return Ok(false);
+        }
+        let data_channel = Self::create_data_channel(
+            &self.connection,
+            channel,
+            self.peer_id,
+            &self.limits,
+            self.updates_tx.clone(),
+        )
+        .await?;
+        self.receive_acks(&data_channel).await;
+        self.outgoing_data_channels.insert(channel, data_channel);
+        Ok(true)
+    }
+
+    /// Queues a keep-alive for the next [`Peer::flush`].
+    pub(crate) fn heartbeat(&mut self) {
+        let frame = self.heartbeat_frame();
+        self.enqueue_frame(Channel::Unreliable, frame);
+    }
+
+    /// A heartbeat carrying when it was sent, in microseconds since the peer
+    /// was created. Only we read it again, the peer sends it back unchanged.
+    fn heartbeat_frame(&self) -> Payload {
+        let sent = self.epoch.elapsed().as_micros() as u64;
+        frame::encode(MessageKind::Heartbeat, &sent.to_be_bytes())
+    }
+
+    fn heartbeat_time(payload: &[u8]) -> Option<Duration> {
+        let micros = u64::from_be_bytes(payload.try_into().ok()?);
+        Some(Duration::from_micros(micros))
+    }
+
+    /// Counts as hearing from the peer, e.g. after it reconnected.
+    pub(crate) fn seen(&self) {
+        *self.last_seen.lock().unwrap() = Instant::now();
+    }
+
+    /// Whether the peer was connected at some point, see [`Heartbeat`].
+    pub(crate) fn established(&self) -> bool {
+        self.established
+    }
+
+    /// Returns whether the peer is responsive if that changed, a peer is
+    /// unresponsive if we heard nothing from it for `timeout`.
+    pub(crate) fn update_responsive(&mut self, timeout: Duration) -> Option<bool> {
+        let responsive = self.last_seen.lock().unwrap().elapsed() <= timeout;
+        if responsive == self.unresponsive {
+            self.unresponsive = !responsive;
+            Some(responsive)
+        } else {
+            None
+        }
+    }
+
+    pub fn state(&self) -> PeerState {
+        *self.state.lock().unwrap()
+    }
+
+    /// Records a lost connection. Returns `false` if it was already lost, e.g.
+    /// when a disconnected peer fails, so a loss is only handled once.
+    pub(crate) fn connection_lost(&mut self) -> bool {
+        self.announced_ready = false;
+        if self.lost_at.is_some() {
+            return false;
+        }
+        self.lost_at = Some(Instant::now());
+        true
+    }
+
+    /// How long ago the connection was lost or last restarted, `None` while
+    /// it is not lost.
+    pub(crate) fn lost_for(&self) -> Option<Duration> {
+        self.lost_at.map(|lost_at| lost_at.elapsed())
+    }
+
+    /// ICE restarts since the peer was last connected.
+    pub(crate) fn restarts(&self) -> u32 {
+        self.restarts
+    }
+
+    /// Returns `true` the first time it is called after the peer (re)connected.
+    pub(crate) fn announce_ready(&mut self) -> bool {
+        !std::mem::replace(&mut self.announced_ready, true)
+    }
+
+    pub(crate) fn connection_restored(&mut self) {
+        self.established = true;
+        self.restarts = 0;
+        self.lost_at = None;
+        self.seen();
+    }
+
+    /// The polite peer gives way when both sides offer at the same time. The
+    /// roles only depend on the two ids, so both sides agree on them.
+    pub fn polite(&self) -> bool {
+        self.id > self.peer_id
+    }
+
+    /// Only the impolite side restarts ICE, otherwise both would send offers at once.
+    pub fn restarts_ice(&self) -> bool {
+        !self.polite()
+    }
+
+    /// Offer with fresh ICE credentials to restore a lost connection. The
+    /// restart is counted and has [`RtcConfig::ice_restart_timeout`] to succeed.
+    pub async fn restart_ice(&mut self) -> Result<PeerMessage> {
+        self.restarts += 1;
+        self.lost_at = Some(Instant::now());
+        let options = RTCOfferOptions {
+            ice_restart: true,
+            ..Default::default()
+        };
+        let offer = self.create_offer_with(Some(options)).await?;
+        Ok(PeerMessage {
+            peer_id: self.peer_id,
+            content: Message::Offer { id: self.id, offer },
+        })
+    }
+
+    /// Starts the handshake, or renegotiates an established connection.
+    pub async fn handshake_offer(&self) -> Result<PeerMessage> {
         let offer = self.create_offer().await?;
         Ok(PeerMessage {
             peer_id: self.peer_id,
@@ -215,11 +545,54 @@ impl Peer {
         })
     }
 
+    /// Answers an offer unless it collides with one we sent ourselves.
+    ///
+    /// On a collision the polite side rolls its offer back, e.g. for a
+    /// renegotiation or an ICE restart. During the first handshake there is
+    /// no connection to keep, so it starts over with a new one instead.
+    pub async fn handle_offer(&self, offer: RTCSessionDescription) -> Result<OfferResponse> {
+        if self.connection.signaling_state() != RTCSignalingState::Stable {
+            if !self.polite() {
+                debug!(
+                    "Ignoring offer from {} that collides with ours",
+                    self.peer_id
+                );
+                return Ok(OfferResponse::Ignored);
+            }
+            if self.connection.current_remote_description().await.is_none() {
+                debug!(
+                    "Offer from {} collides with ours, starting over",
+                    self.peer_id
+                );
+                return Ok(OfferResponse::Reset);
+            }
+            debug!(
+                "Offer from {} collides with ours, rolling ours back",
+                self.peer_id
+            );
+            self.rollback().await?;
+        }
+        Ok(OfferResponse::Answer(self.handshake_accept(offer).await?))
+    }
+
+    /// Withdraws our pending offer, the connection stays as negotiated before.
+    async fn rollback(&self) -> Result<()> {
+        let mut rollback = match self.connection.pending_local_description().await {
+            Some(offer) => offer,
+            None => return Ok(()),
+        };
+        // webrtc parses the description even though a rollback ignores it
+        rollback.sdp_type = RTCSdpType::Rollback;
+        self.connection.set_local_description(rollback).await?;
+        Ok(())
+    }
+
     pub async fn handshake_accept(
         &self,
         offer: RTCSessionDescription,
-    ) -> anyhow::Result<PeerMessage> {
+    ) -> Result<PeerMessage> {
         self.connection.set_remote_description(offer).await?;
+        self.flush_candidates().await;
         let answer = self.connection.create_answer(None).await?;
         self.connection
             .set_local_description(answer.clone())
@@ -233,23 +606,508 @@ impl Peer {
         })
     }
 
-    pub async fn handle_answer(&self, answer: RTCSessionDescription) -> anyhow::Result<()> {
+    pub async fn handle_answer(&self, answer: RTCSessionDescription) -> Result<()> {
         self.connection.set_remote_description(answer).await?;
+        self.flush_candidates().await;
+        Ok(())
+    }
+
+    /// Adds a remote candidate, or queues it until the remote description is
+    /// known since the connection rejects candidates before that.
+    pub async fn add_ice_candidate(&self, candidate: RTCIceCandidateInit) -> Result<()> {
+        if self.connection.remote_description().await.is_none() {
+            self.pending_candidates.lock().unwrap().push(candidate);
+            return Ok(());
+        }
+        self.connection.add_ice_candidate(candidate).await?;
         Ok(())
     }
 
-    pub async fn create_offer(&self) -> anyhow::Result<RTCSessionDescription> {
-        let offer = self.connection.create_offer(None).await?;
+    /// Signals that the remote peer gathered all of its candidates.
+    pub async fn end_of_candidates(&self) -> Result<()> {
+        // An empty candidate is how webrtc marks the end of candidates
+        self.add_ice_candidate(RTCIceCandidateInit::default()).await
+    }
+
+    async fn flush_candidates(&self) {
+        let pending = std::mem::take(&mut *self.pending_candidates.lock().unwrap());
+        for candidate in pending {
+            if let Err(e) = self.connection.add_ice_candidate(candidate).await {
+                warn!("Failed to add ice candidate from {}: {e}", self.peer_id);
+            }
+        }
+    }
+
+    pub async fn create_offer(&self) -> Result<RTCSessionDescription> {
+        self.create_offer_with(None).await
+    }
+
+    async fn create_offer_with(
+        &self,
+        options: Option<RTCOfferOptions>,
+    ) -> Result<RTCSessionDescription> {
+        let offer = self.connection.create_offer(options).await?;
         self.connection.set_local_description(offer).await?;
 
         self.connection
             .local_description()
             .await
-            .ok_or_else(|| anyhow::anyhow!("generate local_description failed!"))
+            .ok_or_else(|| {
+                Error::PeerConnection(webrtc::Error::new(
+                    "generate local_description failed!".to_owned(),
+                ))
+            })
     }
 
-    pub async fn send(&self, payload: Payload) -> anyhow::Result<()> {
-        self.outgoing_data_channel.send(&payload).await?;
+    pub async fn close(&self) {
+        // A replacement for this peer may already be tracked under the same id
+        self.connection
+            .on_peer_connection_state_change(Box::new(|_| Box::pin(async {})))
+            .await;
+        if let Err(e) = self.connection.close().await {
+            warn!("Failed to close connection to {}: {e}", self.peer_id);
+        }
+    }
+
+    pub async fn send(
+        &self,
+        channel: Channel,
+        kind: MessageKind,
+        payload: &[u8],
+    ) -> Result<()> {
+        self.send_frame(channel, &frame::encode(kind, payload))
+            .await
+    }
+
+    async fn send_frame(&self, channel: Channel, frame: &Payload) -> Result<()> {
+        if let Some(data_channel) = self.outgoing_data_channels.get(&channel) {
+            if let Err(e) = data_channel.send(frame).await {
+                self.counters.dropped();
+                return Err(e.into());
+            }
+            self.counters.sent(frame.len());
+        }
         Ok(())
     }
+
+    /// Queues a packet until [`Peer::flush`] sends it.
+    pub(crate) fn enqueue(&mut self, packet: Packet) {
+        let frame = frame::encode(packet.kind, &packet.payload);
+        self.enqueue_frame(packet.channel, frame);
+    }
+
+    /// Queues an encoded frame, e.g. one shared by the recipients of a
+    /// broadcast. A full unreliable queue drops its oldest frame, a full
+    /// reliable queue the new one, the socket stops handing out packets before
+    /// that, see [`Peer::saturated`]. Frames for channels that are not open
+    /// are dropped.
+    pub(crate) fn enqueue_frame(&mut self, channel: Channel, frame: Payload) {
+        if !self.sends_on(channel) {
+            self.counters.dropped();
+            debug!(
+                "Channel '{}' to {} is not open, dropping packet",
+                channel.label(),
+                self.peer_id
+            );
+            return;
+        }
+        let queue = self.outgoing.entry(channel).or_default();
+        if !channel.is_reliable() {
+            if queue.len() >= self.limits.unreliable && queue.pop_front().is_some() {
+                self.counters.dropped();
+                debug!("Unreliable queue of {} is full", self.peer_id);
+            }
+            queue.push_back(frame);
+        } else if queue.len() >= self.limits.reliable {
+            self.counters.dropped();
+            warn!(
+                "Reliable queue of {} is full, dropping packet",
+                self.peer_id
+            );
+        } else {
+            queue.push_back(frame);
+        }
+    }
+
+    /// Whether a reliable queue is full. The socket leaves further packets in
+    /// its own queue until the peer catches up, so senders see
+    /// [`Error::QueueFull`] instead of losing reliable packets.
+    pub(crate) fn saturated(&self) -> bool {
+        self.outgoing
+            .iter()
+            .any(|(channel, queue)| channel.is_reliable() && queue.len() >= self.limits.reliable)
+    }
+
+    /// Sends queued packets until a data channel buffers more than
+    /// [`QueueLimits::buffered_high`], the rest waits for
+    /// [`PeerUpdate::Drained`]. Packets that fail are counted as dropped.
+    pub(crate) async fn flush(&mut self) {
+        let data_channels: Vec<_> = self
+            .outgoing_data_channels
+            .iter()
+            .map(|(&channel, data_channel)| (channel, data_channel.clone()))
+            .collect();
+        for (channel, data_channel) in data_channels {
+            while data_channel.buffered_amount().await < self.limits.buffered_high {
+                let frame = match self
+                    .outgoing
+                    .get_mut(&channel)
+                    .and_then(VecDeque::pop_front)
+                {
+                    Some(frame) => frame,
+                    None => break,
+                };
+                if let Err(e) = self.send_frame(channel, &frame).await {
+                    warn!("Dropping packet for {}: {e}", self.peer_id);
+                }
+            }
+        }
+    }
+
+    /// Our own counters plus what webrtc reports about the connection.
+    pub async fn stats(&self) -> PeerStats {
+        let mut stats = self.counters.stats();
+        // Replaced by the candidate pair's measurement once there is one
+        stats.rtt = *self.rtt.lock().unwrap();
+        stats.queued = self.outgoing.values().map(VecDeque::len).sum();
+        for data_channel in self.outgoing_data_channels.values() {
+            stats.buffered_amount += data_channel.buffered_amount().await;
+        }
+
+        let report = self.connection.get_stats().await;
+        let selected = report.reports.values().find_map(|report| match report {
+            StatsReportType::CandidatePair(pair) if pair.nominated => Some(pair),
+            _ => None,
+        });
+        if let Some(pair) = selected {
+            // Seconds, zero until the first STUN response arrived
+            let rtt = pair.current_round_trip_time;
+            if rtt.is_finite() && rtt > 0.0 {
+                stats.rtt = Some(Duration::from_secs_f64(rtt));
+            }
+            stats.candidate_type = report.reports.values().find_map(|report| match report {
+                StatsReportType::LocalCandidate(candidate)
+                    if candidate.id == pair.local_candidate_id =>
+                {
+                    CandidateType::from_ice(candidate.candidate_type)
+                }
+                _ => None,
+            });
+        }
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, sync::Arc, time::Duration};
+
+    use tokio::{sync::mpsc, time};
+    use uuid::Uuid;
+    use webrtc::{
+        ice_transport::ice_candidate::RTCIceCandidateInit,
+        peer_connection::{
+            sdp::session_description::RTCSessionDescription, signaling_state::RTCSignalingState,
+        },
+    };
+
+    use super::{Channel, OfferResponse, Peer, QueueLimits, RtcConfigBuilder};
+    use crate::{
+        frame::{Inbox, MessageKind, PacketReceiver},
+        message::{Message, PeerMessage},
+        stats::CandidateType,
+        Packet, Payload,
+    };
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// Forwards trickled candidates, most of them arrive before the receiving
+    /// side knows the remote description.
+    fn forward_candidates(mut rx: mpsc::UnboundedReceiver<PeerMessage>, peer: Arc<Peer>) {
+        tokio::spawn(async move {
+            while let Some(msg) = rx.recv().await {
+                match msg.content {
+                    Message::IceCandidate { candidate, .. } => {
+                        peer.add_ice_candidate(candidate).await.unwrap()
+                    }
+                    Message::EndOfCandidates { .. } => peer.end_of_candidates().await.unwrap(),
+                    _ => {}
+                }
+            }
+        });
+    }
+
+    struct TestPeer {
+        peer: Peer,
+        ws_rx: mpsc::UnboundedReceiver<PeerMessage>,
+        data_rx: PacketReceiver,
+    }
+
+    async fn new_peer(id: Uuid, peer_id: Uuid) -> TestPeer {
+        new_peer_with(id, peer_id, RtcConfigBuilder::new()).await
+    }
+
+    async fn new_peer_with(id: Uuid, peer_id: Uuid, config: RtcConfigBuilder) -> TestPeer {
+        let config = config.ice_servers(vec![]).build();
+        let (ws_tx, ws_rx) = mpsc::unbounded_channel();
+        let (updates_tx, _updates_rx) = mpsc::unbounded_channel();
+        let (inbox, data_rx, _ggrs_rx, _app_rx) = Inbox::new(64);
+        let peer = Peer::new(id, peer_id, &config, ws_tx, inbox, updates_tx)
+            .await
+            .unwrap();
+        TestPeer {
+            peer,
+            ws_rx,
+            data_rx,
+        }
+    }
+
+    fn offer(msg: PeerMessage) -> RTCSessionDescription {
+        match msg.content {
+            Message::Offer { offer, .. } => offer,
+            msg => panic!("Expected offer, got {msg:?}"),
+        }
+    }
+
+    fn answer(msg: PeerMessage) -> RTCSessionDescription {
+        match msg.content {
+            Message::Answer { answer, .. } => answer,
+            msg => panic!("Expected answer, got {msg:?}"),
+        }
+    }
+
+    type ConnectedPair = (Arc<Peer>, Arc<Peer>, PacketReceiver, PacketReceiver);
+
+    /// Exchanges candidates and waits until both peers are ready, the
+    /// offer/answer handshake is up to the caller.
+    async fn connect(a: TestPeer, b: TestPeer) -> ConnectedPair {
+        let (a_peer, b_peer) = (Arc::new(a.peer), Arc::new(b.peer));
+        forward_candidates(a.ws_rx, b_peer.clone());
+        forward_candidates(b.ws_rx, a_peer.clone());
+        time::timeout(TIMEOUT, async {
+            while !(a_peer.ready().await && b_peer.ready().await) {
+                time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .expect("Peers did not connect");
+        (a_peer, b_peer, a.data_rx, b.data_rx)
+    }
+
+    async fn connected_pair() -> ConnectedPair {
+        let (a_id, b_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let a = new_peer(a_id, b_id).await;
+        let b = new_peer(b_id, a_id).await;
+
+        let offer = offer(a.peer.handshake_offer().await.unwrap());
+        let answer = answer(b.peer.handshake_accept(offer).await.unwrap());
+        a.peer.handle_answer(answer).await.unwrap();
+        connect(a, b).await
+    }
+
+    async fn receive(rx: &mut PacketReceiver) -> (Channel, Payload) {
+        let packet = time::timeout(TIMEOUT, rx.recv())
+            .await
+            .expect("No packet received")
+            .unwrap();
+        (packet.channel, packet.payload)
+    }
+
+    #[tokio::test]
+    async fn reliable_and_unreliable_channels() {
+        let (a, b, mut a_rx, mut b_rx) = connected_pair().await;
+        let expected: HashSet<(Channel, Payload)> = [
+            (Channel::Reliable, Payload::from_static(b"chat")),
+            (Channel::Unreliable, Payload::from_static(b"input")),
+        ]
+        .into_iter()
+        .collect();
+
+        for (channel, payload) in &expected {
+            a.send(*channel, MessageKind::Raw, payload).await.unwrap();
+            b.send(*channel, MessageKind::Raw, payload).await.unwrap();
+        }
+
+        let from_a: HashSet<_> = [receive(&mut b_rx).await, receive(&mut b_rx).await]
+            .into_iter()
+            .collect();
+        let from_b: HashSet<_> = [receive(&mut a_rx).await, receive(&mut a_rx).await]
+            .into_iter()
+            .collect();
+        assert_eq!(from_a, expected);
+        assert_eq!(from_b, expected);
+    }
+
+    #[tokio::test]
+    async fn stats_count_traffic() {
+        let (a, b, _a_rx, mut b_rx) = connected_pair().await;
+        a.send(Channel::Reliable, MessageKind::Raw, b"chat")
+            .await
+            .unwrap();
+        receive(&mut b_rx).await;
+
+        let sent = a.stats().await;
+        assert_eq!((sent.packets_sent, sent.bytes_sent), (1, 5));
+        assert_eq!(sent.candidate_type, Some(CandidateType::Host));
+        let received = b.stats().await;
+        assert_eq!((received.packets_received, received.bytes_received), (1, 5));
+        assert_eq!(received.undecodable, 0);
+
+        // Unreliable, so send heartbeats until one comes back
+        time::timeout(TIMEOUT, async {
+            while a.stats().await.rtt.is_none() {
+                a.send_frame(Channel::Unreliable, &a.heartbeat_frame())
+                    .await
+                    .unwrap();
+                time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .expect("No heartbeat came back");
+        assert!(a.stats().await.rtt.is_some());
+    }
+
+    #[tokio::test]
+    async fn full_queues_drop_packets() {
+        let limits = QueueLimits {
+            unreliable: 2,
+            reliable: 2,
+            ..Default::default()
+        };
+        let (a_id, b_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut a = new_peer_with(a_id, b_id, RtcConfigBuilder::new().queue_limits(limits))
+            .await
+            .peer;
+        for channel in Channel::ALL {
+            for byte in 1..=3 {
+                a.enqueue(Packet::new(b_id, channel, vec![byte]));
+            }
+        }
+
+        let payloads = |channel: Channel| -> Vec<u8> {
+            // Behind the one byte frame header
+            a.outgoing[&channel].iter().map(|frame| frame[1]).collect()
+        };
+        assert_eq!(payloads(Channel::Unreliable), [2, 3]);
+        assert_eq!(payloads(Channel::Reliable), [1, 2]);
+        assert!(a.saturated());
+        let stats = a.stats().await;
+        assert_eq!((stats.queued, stats.dropped), (4, 2));
+    }
+
+    #[tokio::test]
+    async fn silent_peers_become_unresponsive() {
+        let mut peer = new_peer(Uuid::new_v4(), Uuid::new_v4()).await.peer;
+        assert_eq!(peer.update_responsive(TIMEOUT), None);
+
+        time::sleep(Duration::from_millis(20)).await;
+        let timeout = Duration::from_millis(10);
+        assert_eq!(peer.update_responsive(timeout), Some(false));
+        assert_eq!(peer.update_responsive(timeout), None);
+
+        peer.seen();
+        assert_eq!(peer.update_responsive(timeout), Some(true));
+    }
+
+    #[tokio::test]
+    async fn simultaneous_offers_are_resolved() {
+        let mut ids = [Uuid::new_v4(), Uuid::new_v4()];
+        ids.sort();
+        let [impolite_id, polite_id] = ids;
+        let impolite = new_peer(impolite_id, polite_id).await;
+        let polite = new_peer(polite_id, impolite_id).await;
+        assert!(polite.peer.polite() && !impolite.peer.polite());
+
+        let impolite_offer = offer(impolite.peer.handshake_offer().await.unwrap());
+        let polite_offer = offer(polite.peer.handshake_offer().await.unwrap());
+        assert!(matches!(
+            impolite.peer.handle_offer(polite_offer).await.unwrap(),
+            OfferResponse::Ignored
+        ));
+        assert!(matches!(
+            polite
+                .peer
+                .handle_offer(impolite_offer.clone())
+                .await
+                .unwrap(),
+            OfferResponse::Reset
+        ));
+
+        // The polite side starts over, like the socket does
+        polite.peer.close().await;
+        let polite = new_peer(polite_id, impolite_id).await;
+        let answer = match polite.peer.handle_offer(impolite_offer).await.unwrap() {
+            OfferResponse::Answer(msg) => answer(msg),
+            response => panic!("Expected answer, got {response:?}"),
+        };
+        impolite.peer.handle_answer(answer).await.unwrap();
+        connect(impolite, polite).await;
+    }
+
+    #[tokio::test]
+    async fn established_connections_renegotiate() {
+        let (a, b, _a_rx, mut b_rx) = connected_pair().await;
+        let offer = offer(a.handshake_offer().await.unwrap());
+        let answer = match b.handle_offer(offer).await.unwrap() {
+            OfferResponse::Answer(msg) => answer(msg),
+            response => panic!("Expected answer, got {response:?}"),
+        };
+        a.handle_answer(answer).await.unwrap();
+
+        a.send(Channel::Reliable, MessageKind::Raw, b"still here")
+            .await
+            .unwrap();
+        assert_eq!(
+            receive(&mut b_rx).await,
+            (Channel::Reliable, Payload::from_static(b"still here"))
+        );
+    }
+
+    #[tokio::test]
+    async fn renegotiation_collisions_keep_the_connection() {
+        let (a, b, a_rx, b_rx) = connected_pair().await;
+        let (polite, impolite, mut polite_rx) = if a.polite() {
+            (a, b, a_rx)
+        } else {
+            (b, a, b_rx)
+        };
+
+        let impolite_offer = offer(impolite.handshake_offer().await.unwrap());
+        let polite_offer = offer(polite.handshake_offer().await.unwrap());
+        assert!(matches!(
+            impolite.handle_offer(polite_offer).await.unwrap(),
+            OfferResponse::Ignored
+        ));
+        let answer = match polite.handle_offer(impolite_offer).await.unwrap() {
+            OfferResponse::Answer(msg) => answer(msg),
+            response => panic!("Expected answer, got {response:?}"),
+        };
+        impolite.handle_answer(answer).await.unwrap();
+        for peer in [&polite, &impolite] {
+            assert_eq!(peer.connection.signaling_state(), RTCSignalingState::Stable);
+        }
+
+        impolite
+            .send(Channel::Reliable, MessageKind::Raw, b"still here")
+            .await
+            .unwrap();
+        assert_eq!(
+            receive(&mut polite_rx).await,
+            (Channel::Reliable, Payload::from_static(b"still here"))
+        );
+    }
+
+    #[tokio::test]
+    async fn early_candidates_are_queued() {
+        let peer = new_peer(Uuid::new_v4(), Uuid::new_v4()).await.peer;
+        let candidate = RTCIceCandidateInit {
+            candidate: "candidate:1 1 udp 2130706431 127.0.0.1 5000 typ host".to_owned(),
+            sdp_mid: Some("0".to_owned()),
+            sdp_mline_index: Some(0),
+            ..Default::default()
+        };
+        peer.add_ice_candidate(candidate).await.unwrap();
+        peer.end_of_candidates().await.unwrap();
+        assert_eq!(peer.pending_candidates.lock().unwrap().len(), 2);
+    }
 }
@@ -0,0 +1,69 @@
+use webrtc::data_channel::data_channel_init::RTCDataChannelInit;
+
+/// Data channels of a peer connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum Channel {
+    /// Ordered and retransmitted until delivered, e.g. chat, lobby state or save transfers.
+    Reliable,
+    /// Unordered without retransmits, e.g. GGRS inputs.
+    Unreliable,
+    /// Opened later on an established connection, see
+    /// [`crate::message::StateMessage::OpenChannel`], e.g. for a file transfer
+    /// that should not hold up the chat.
+    Custom { id: u8, reliable: bool },
+}
+
+impl Channel {
+    /// Channels negotiated with every peer.
+    pub const ALL: [Channel; 2] = [Channel::Reliable, Channel::Unreliable];
+
+    pub fn label(&self) -> String {
+        match self {
+            Channel::Reliable => "reliable".to_owned(),
+            Channel::Unreliable => "unreliable".to_owned(),
+            Channel::Custom { id, reliable } => {
+                let reliability = if *reliable { "reliable" } else { "unreliable" };
+                format!("custom-{id}-{reliability}")
+            }
+        }
+    }
+
+    pub fn from_label(label: &str) -> Option<Self> {
+        if let Some(channel) = Self::ALL.into_iter().find(|c| c.label() == label) {
+            return Some(channel);
+        }
+        let (id, reliability) = label.strip_prefix("custom-")?.split_once('-')?;
+        let reliable = match reliability {
+            "reliable" => true,
+            "unreliable" => false,
+            _ => return None,
+        };
+        Some(Channel::Custom {
+            id: id.parse().ok()?,
+            reliable,
+        })
+    }
+
+    /// Whether packets arrive exactly once and in the order they were sent.
+    pub fn is_reliable(&self) -> bool {
+        matches!(
+            self,
+            Channel::Reliable | Channel::Custom { reliable: true, .. }
+        )
+    }
+
+    pub(crate) fn init(&self) -> RTCDataChannelInit {
+        if self.is_reliable() {
+            RTCDataChannelInit {
+                ordered: Some(true),
+                ..Default::default()
+            }
+        } else {
+            RTCDataChannelInit {
+                ordered: Some(false),
+                max_retransmits: Some(0),
+                ..Default::default()
+            }
+        }
+    }
+}
@@ -1,14 +1,134 @@
-use std::fmt;
+use std::{fmt, time::Duration};
 
 use secrecy::{ExposeSecret, Secret};
 use webrtc::ice_transport::ice_server::RTCIceServer;
 
+use crate::signaling::SignalingClient;
+
+/// Exponential backoff used to reconnect to the signaling server.
+#[derive(Debug, Clone)]
+pub struct Reconnect {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    /// Give up after this many failed attempts, `None` retries forever.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for Reconnect {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(250),
+            max_delay: Duration::from_secs(8),
+            max_attempts: Some(10),
+        }
+    }
+}
+
+/// Keep-alives on the data channels, so peers that vanish without the
+/// signaling server noticing are found before SCTP gives up on them.
+#[derive(Debug, Clone)]
+pub struct Heartbeat {
+    /// How often keep-alives are sent, zero disables heartbeats.
+    pub interval: Duration,
+    /// A peer that sent nothing for this long is unresponsive.
+    pub timeout: Duration,
+    /// Removes unresponsive peers instead of waiting for them to answer again.
+    pub remove_unresponsive: bool,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(1),
+            timeout: Duration::from_secs(5),
+            remove_unresponsive: false,
+        }
+    }
+}
+
+impl Heartbeat {
+    /// No keep-alives, peers are only dropped when their connection fails.
+    pub fn disabled() -> Self {
+        Self {
+            interval: Duration::ZERO,
+            ..Default::default()
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.interval.is_zero()
+    }
+}
+
+/// How much a socket buffers before it drops packets, so a stalled peer or
+/// application can't make memory grow without limit.
+#[derive(Debug, Clone)]
+pub struct QueueLimits {
+    /// Packets handed to the socket that its run loop did not take yet.
+    pub outgoing: usize,
+    /// Received packets of each kind the application did not take yet. Above
+    /// it the oldest unreliable or GGRS packet makes room, reliable packets
+    /// are dropped if there is none.
+    pub incoming: usize,
+    /// Requests like closing the socket that are not handled yet.
+    pub control: usize,
+    /// Unreliable packets waiting for a peer, the oldest are dropped above it.
+    pub unreliable: usize,
+    /// Reliable packets waiting for a peer. Above it the socket stops taking
+    /// packets from the application until the peer catches up.
+    pub reliable: usize,
+    /// Bytes a data channel may buffer before we stop sending to it.
+    pub buffered_high: usize,
+    /// Sending resumes once the data channel buffers less than this.
+    pub buffered_low: usize,
+}
+
+impl Default for QueueLimits {
+    fn default() -> Self {
+        Self {
+            outgoing: 4096,
+            incoming: 4096,
+            control: 64,
+            unreliable: 64,
+            reliable: 1024,
+            buffered_high: 1024 * 1024,
+            buffered_low: 256 * 1024,
+        }
+    }
+}
+
+const DEFAULT_ICE_RESTART_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub struct RtcConfig {
     pub address: String,
     pub port: u16,
     pub user: String,
     pub password: Secret<Option<String>>,
     pub ice_servers: Vec<RTCIceServer>,
+    pub reconnect: Reconnect,
+    /// How long an ICE restart may take before the next one. The polite side
+    /// does not restart, it gives up once the other side would have.
+    pub ice_restart_timeout: Duration,
+    pub heartbeat: Heartbeat,
+    pub queue_limits: QueueLimits,
+    pub signaling_client: SignalingClient,
+}
+
+impl Clone for RtcConfig {
+    fn clone(&self) -> Self {
+        Self {
+            address: self.address.clone(),
+            port: self.port,
+            user: self.user.clone(),
+            password: Secret::new(self.password.expose_secret().clone()),
+            ice_servers: self.ice_servers.clone(),
+            reconnect: self.reconnect.clone(),
+            ice_restart_timeout: self.ice_restart_timeout,
+            heartbeat: self.heartbeat.clone(),
+            queue_limits: self.queue_limits.clone(),
+            signaling_client: self.signaling_client,
+        }
+    }
 }
 
 impl fmt::Debug for RtcConfig {
@@ -18,6 +138,11 @@ impl fmt::Debug for RtcConfig {
             .field("port", &self.port)
             .field("user", &self.user)
             .field("ice_servers", &self.ice_servers)
+            .field("reconnect", &self.reconnect)
+            .field("ice_restart_timeout", &self.ice_restart_timeout)
+            .field("heartbeat", &self.heartbeat)
+            .field("queue_limits", &self.queue_limits)
+            .field("signaling_client", &self.signaling_client)
             .finish()
     }
 }
@@ -34,6 +159,11 @@ impl Default for RtcConfig {
             user: Default::default(),
             password: Secret::new(None),
             ice_servers,
+            reconnect: Default::default(),
+            ice_restart_timeout: DEFAULT_ICE_RESTART_TIMEOUT,
+            heartbeat: Default::default(),
+            queue_limits: Default::default(),
+            signaling_client: Default::default(),
         }
     }
 }
@@ -43,6 +173,10 @@ impl RtcConfig {
         self.password = Secret::new(Some(password.as_ref().to_string()));
     }
 
+    pub(crate) fn password(&self) -> Option<String> {
+        self.password.expose_secret().clone()
+    }
+
     pub fn take_password(&mut self) -> Option<String> {
         let password = self.password.expose_secret().clone();
         self.password = Secret::new(None);
@@ -64,6 +198,11 @@ pub struct RtcConfigBuilder {
     pub user: String,
     pub password: Secret<Option<String>>,
     pub ice_servers: Vec<RTCIceServer>,
+    pub reconnect: Reconnect,
+    pub ice_restart_timeout: Duration,
+    pub heartbeat: Heartbeat,
+    pub queue_limits: QueueLimits,
+    pub signaling_client: SignalingClient,
 }
 
 impl Default for RtcConfigBuilder {
@@ -78,6 +217,11 @@ impl Default for RtcConfigBuilder {
             user: Default::default(),
             password: Secret::new(None),
             ice_servers,
+            reconnect: Default::default(),
+            ice_restart_timeout: DEFAULT_ICE_RESTART_TIMEOUT,
+            heartbeat: Default::default(),
+            queue_limits: Default::default(),
+            signaling_client: Default::default(),
         }
     }
 }
@@ -94,6 +238,11 @@ impl RtcConfigBuilder {
             user: self.user,
             password: self.password,
             ice_servers: self.ice_servers,
+            reconnect: self.reconnect,
+            ice_restart_timeout: self.ice_restart_timeout,
+            heartbeat: self.heartbeat,
+            queue_limits: self.queue_limits,
+            signaling_client: self.signaling_client,
         }
     }
 
@@ -121,4 +270,29 @@ impl RtcConfigBuilder {
         self.ice_servers = ice_servers;
         self
     }
+
+    pub fn reconnect(mut self, reconnect: Reconnect) -> Self {
+        self.reconnect = reconnect;
+        self
+    }
+
+    pub fn ice_restart_timeout(mut self, timeout: Duration) -> Self {
+        self.ice_restart_timeout = timeout;
+        self
+    }
+
+    pub fn heartbeat(mut self, heartbeat: Heartbeat) -> Self {
+        self.heartbeat = heartbeat;
+        self
+    }
+
+    pub fn queue_limits(mut self, queue_limits: QueueLimits) -> Self {
+        self.queue_limits = queue_limits;
+        self
+    }
+
+    pub fn signaling_client(mut self, signaling_client: SignalingClient) -> Self {
+        self.signaling_client = signaling_client;
+        self
+    }
 }
@@ -0,0 +1,26 @@
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+
+/// Lifecycle of the connection to a single peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PeerState {
+    New,
+    Connecting,
+    Connected,
+    /// Connectivity was lost, an ICE restart may bring it back.
+    Disconnected,
+    Failed,
+    Closed,
+}
+
+impl From<RTCPeerConnectionState> for PeerState {
+    fn from(state: RTCPeerConnectionState) -> Self {
+        match state {
+            RTCPeerConnectionState::Unspecified | RTCPeerConnectionState::New => PeerState::New,
+            RTCPeerConnectionState::Connecting => PeerState::Connecting,
+            RTCPeerConnectionState::Connected => PeerState::Connected,
+            RTCPeerConnectionState::Disconnected => PeerState::Disconnected,
+            RTCPeerConnectionState::Failed => PeerState::Failed,
+            RTCPeerConnectionState::Closed => PeerState::Closed,
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use tokio::sync::mpsc::Sender;
+
+use crate::{
+    broadcast::{Broadcast, Outgoing, Recipients},
+    error::Result,
+    frame::{MessageKind, PacketReceiver},
+    peer::Channel,
+    Packet, Payload, WebRTCSocket,
+};
+
+/// Untyped bytes sent alongside GGRS traffic over the same peer connections.
+///
+/// Unlike [`WebRTCSocket::send_data`] and [`WebRTCSocket::receive_data`] it
+/// does not borrow the socket, so it works while [`WebRTCSocket::run`] runs.
+#[derive(Debug)]
+pub struct RawSocket {
+    in_data_rx: PacketReceiver,
+    out_data_tx: Sender<Outgoing>,
+}
+
+impl RawSocket {
+    /// `None` if the raw packets were already taken, there is only one
+    /// receiver per socket.
+    pub fn new(webrtc_socket: &mut WebRTCSocket) -> Option<Self> {
+        let in_data_rx = webrtc_socket.in_data_rx()?;
+        Some(Self::from_channels(in_data_rx, webrtc_socket.out_data_tx()))
+    }
+
+    pub(crate) fn from_channels(in_data_rx: PacketReceiver, out_data_tx: Sender<Outgoing>) -> Self {
+        Self {
+            in_data_rx,
+            out_data_tx,
+        }
+    }
+
+    /// Fails with [`crate::Error::QueueFull`] if the socket does not keep up,
+    /// see [`crate::peer::QueueLimits::outgoing`].
+    pub fn send_data(&mut self, packet: Packet) -> Result<()> {
+        self.out_data_tx.try_send(packet.into())?;
+        Ok(())
+    }
+
+    /// Sends the payload to several of the ready peers. Fails like
+    /// [`RawSocket::send_data`].
+    pub fn broadcast(&mut self, to: Recipients, channel: Channel, payload: Payload) -> Result<()> {
+        let broadcast = Broadcast {
+            to,
+            channel,
+            kind: MessageKind::Raw,
+            payload,
+        };
+        self.out_data_tx.try_send(broadcast.into())?;
+        Ok(())
+    }
+
+    /// Packets received since the last call, never blocks.
+    pub fn receive_data(&mut self) -> Vec<Packet> {
+        std::iter::from_fn(|| self.in_data_rx.try_recv().ok()).collect()
+    }
+
+    /// Waits for the next packet, `None` once the socket is gone.
+    pub async fn recv(&mut self) -> Option<Packet> {
+        self.in_data_rx.recv().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::RawSocket;
+    use crate::{
+        peer::{Channel, RtcConfigBuilder},
+        testing::{connect, hub_sockets, run_until, TIMEOUT},
+        Packet, Payload,
+    };
+
+    #[tokio::test]
+    async fn raw_packets_between_sockets() {
+        let mut sockets = hub_sockets::<2>(RtcConfigBuilder::new()).await;
+        let (alice_id, bob_id) = (sockets[0].id(), sockets[1].id());
+        let mut alice_raw = RawSocket::new(&mut sockets[0]).unwrap();
+        let mut bob_raw = RawSocket::new(&mut sockets[1]).unwrap();
+        assert!(RawSocket::new(&mut sockets[0]).is_none());
+        connect(&mut sockets).await;
+
+        let exchange = async {
+            let sent_at = Instant::now();
+            let packet = Packet::new(bob_id, Channel::Reliable, Payload::from_static(b"ping"));
+            assert_eq!(packet.received_at(), None);
+            alice_raw.send_data(packet).unwrap();
+            let packet = bob_raw.recv().await.unwrap();
+            assert_eq!(packet.id(), alice_id);
+            assert_eq!(packet.channel(), Channel::Reliable);
+            assert!(packet.channel().is_reliable());
+            assert_eq!(packet.payload(), &Payload::from_static(b"ping"));
+            assert!(packet.received_at().unwrap() >= sent_at);
+
+            let packet = Packet::new(alice_id, Channel::Reliable, vec![1, 2, 3]);
+            bob_raw.send_data(packet).unwrap();
+            let packet = alice_raw.recv().await.unwrap();
+            assert_eq!(packet.id(), bob_id);
+            assert_eq!(packet.into_payload(), Payload::from(vec![1, 2, 3]));
+            assert!(alice_raw.receive_data().is_empty());
+        };
+        run_until(&mut sockets, TIMEOUT, exchange).await;
+    }
+}
@@ -0,0 +1,149 @@
+use bytes::Bytes;
+use futures_util::{Sink, Stream};
+
+use crate::{error::Result, peer::RtcConfig, Error};
+
+#[cfg(feature = "awc")]
+mod awc_transport;
+#[cfg(feature = "tungstenite")]
+mod tungstenite_transport;
+
+#[cfg(not(any(feature = "awc", feature = "tungstenite")))]
+compile_error!("Enable at least one signaling client, feature \"awc\" or \"tungstenite\"");
+
+/// A websocket frame on the signaling connection, independent of the client
+/// that carries it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+    Text(String),
+    Binary(Bytes),
+    Ping(Bytes),
+    Pong(Bytes),
+    Close,
+}
+
+/// Connection to the signaling server. It is `Send`, so a socket using it can
+/// be spawned on any tokio runtime.
+pub trait SignalingTransport:
+    Stream<Item = Result<Frame>> + Sink<Frame, Error = Error> + Unpin + Send
+{
+}
+
+impl<T> SignalingTransport for T where
+    T: Stream<Item = Result<Frame>> + Sink<Frame, Error = Error> + Unpin + Send
+{
+}
+
+pub type SignalingConnection = Box<dyn SignalingTransport + Send>;
+
+/// Websocket client used to talk to the signaling server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalingClient {
+    /// actix' awc, connecting needs a `tokio::task::LocalSet` to run the
+    /// websocket on. The connection itself can be moved to other threads.
+    #[cfg(feature = "awc")]
+    Awc,
+    /// tokio-tungstenite, runs on any tokio runtime.
+    #[cfg(feature = "tungstenite")]
+    Tungstenite,
+}
+
+/// tungstenite if it is enabled, so the default works on any runtime.
+impl Default for SignalingClient {
+    fn default() -> Self {
+        #[cfg(feature = "tungstenite")]
+        {
+            SignalingClient::Tungstenite
+        }
+        #[cfg(not(feature = "tungstenite"))]
+        {
+            SignalingClient::Awc
+        }
+    }
+}
+
+/// Credentials for servers that use basic authentication like the matchmaker.
+#[derive(Clone, Copy)]
+struct BasicAuth<'a> {
+    user: &'a str,
+    password: Option<&'a str>,
+}
+
+/// Opens a signaling connection to the matchmaker's login route with the
+/// client selected in `rtc_config`.
+pub async fn connect(rtc_config: &RtcConfig) -> Result<SignalingConnection> {
+    let password = rtc_config.password();
+    let auth = BasicAuth {
+        user: &rtc_config.user,
+        password: password.as_deref(),
+    };
+    open(
+        rtc_config.signaling_client,
+        &rtc_config.login_url(),
+        Some(auth),
+    )
+    .await
+}
+
+/// Opens a signaling connection to `url` without credentials.
+pub async fn connect_url(client: SignalingClient, url: &str) -> Result<SignalingConnection> {
+    open(client, url, None).await
+}
+
+async fn open(
+    client: SignalingClient,
+    url: &str,
+    auth: Option<BasicAuth<'_>>,
+) -> Result<SignalingConnection> {
+    match client {
+        #[cfg(feature = "awc")]
+        SignalingClient::Awc => awc_transport::connect(url, auth).await,
+        #[cfg(feature = "tungstenite")]
+        SignalingClient::Tungstenite => tungstenite_transport::connect(url, auth).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SignalingConnection;
+
+    fn assert_send<T: Send>() {}
+
+    #[test]
+    fn connections_are_send() {
+        assert_send::<SignalingConnection>();
+    }
+
+    /// The default client must not need a `LocalSet`.
+    #[cfg(feature = "tungstenite")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn default_client_logs_in_on_a_multi_threaded_runtime() {
+        use std::net::Ipv4Addr;
+
+        use futures_util::SinkExt;
+        use tokio::net::TcpListener;
+        use tokio_tungstenite::tungstenite;
+        use uuid::Uuid;
+
+        use crate::{message::Message, peer::RtcConfigBuilder, WebRTCSocket};
+
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let id = Uuid::new_v4();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+            let msg = serde_json::to_string(&Message::Id(id)).unwrap();
+            ws.send(tungstenite::Message::Text(msg)).await.unwrap();
+            ws
+        });
+
+        let rtc_config = RtcConfigBuilder::new().port(port).build();
+        let socket = tokio::spawn(WebRTCSocket::new(rtc_config))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(socket.id(), id);
+        drop(server.await.unwrap());
+    }
+}
@@ -0,0 +1,151 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use awc::{error::WsProtocolError, ws};
+use bytes::Bytes;
+use futures_util::{Sink, SinkExt, Stream, StreamExt};
+use tokio::{
+    select,
+    sync::{
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+};
+
+use super::{BasicAuth, Frame, SignalingConnection};
+use crate::{error::Result, Error};
+
+/// awc is not `Send`, so the websocket lives in a task on the current
+/// `LocalSet` and the returned connection talks to it over channels.
+pub(super) async fn connect(url: &str, auth: Option<BasicAuth<'_>>) -> Result<SignalingConnection> {
+    let url = url.to_owned();
+    let auth = auth.map(|auth| (auth.user.to_owned(), auth.password.map(str::to_owned)));
+    let (connected_tx, connected_rx) = oneshot::channel();
+    tokio::task::spawn_local(async move {
+        let mut request = awc::Client::new().ws(url);
+        if let Some((user, password)) = auth {
+            request = request.basic_auth(user, password.as_deref());
+        }
+        let ws = match request.connect().await {
+            Ok((_res, ws)) => ws,
+            Err(e) => {
+                let _ = connected_tx.send(Err(e.into()));
+                return;
+            }
+        };
+        let (incoming_tx, incoming) = mpsc::unbounded_channel();
+        let (outgoing, outgoing_rx) = mpsc::unbounded_channel();
+        let bridge = Bridge {
+            incoming,
+            outgoing: Some(outgoing),
+        };
+        if connected_tx.send(Ok(bridge)).is_ok() {
+            pump(ws, outgoing_rx, incoming_tx).await;
+        }
+    });
+    let bridge = connected_rx.await.map_err(|_| Error::ChannelClosed)??;
+    Ok(Box::new(bridge))
+}
+
+/// Moves frames between the websocket and the [`Bridge`] until either side
+/// is closed.
+async fn pump<S>(
+    ws: S,
+    mut outgoing: UnboundedReceiver<Frame>,
+    incoming: UnboundedSender<Result<Frame>>,
+) where
+    S: Stream<Item = Result<ws::Frame, WsProtocolError>>
+        + Sink<ws::Message, Error = WsProtocolError>,
+{
+    let (mut sink, mut stream) = ws.split();
+    loop {
+        select! {
+            frame = outgoing.recv() => match frame {
+                Some(frame) => {
+                    if let Err(e) = sink.send(to_message(frame)).await {
+                        let _ = incoming.send(Err(e.into()));
+                        return;
+                    }
+                }
+                None => {
+                    let _ = sink.close().await;
+                    return;
+                }
+            },
+            frame = stream.next() => match frame {
+                Some(frame) => {
+                    if incoming.send(frame.map(from_frame).map_err(Error::from)).is_err() {
+                        return;
+                    }
+                }
+                None => return,
+            },
+        }
+    }
+}
+
+/// The socket's end of the websocket task.
+struct Bridge {
+    incoming: UnboundedReceiver<Result<Frame>>,
+    /// Dropped on close, which makes the task close the websocket.
+    outgoing: Option<UnboundedSender<Frame>>,
+}
+
+impl Stream for Bridge {
+    type Item = Result<Frame>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.incoming.poll_recv(cx)
+    }
+}
+
+impl Sink<Frame> for Bridge {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, frame: Frame) -> Result<()> {
+        self.outgoing
+            .as_ref()
+            .and_then(|outgoing| outgoing.send(frame).ok())
+            .ok_or_else(|| Error::ConnectionLost("Signaling connection closed".to_owned()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.outgoing = None;
+        Poll::Ready(Ok(()))
+    }
+}
+
+fn from_frame(frame: ws::Frame) -> Frame {
+    match frame {
+        ws::Frame::Text(text) => match String::from_utf8(text.to_vec()) {
+            Ok(text) => Frame::Text(text),
+            Err(_) => Frame::Binary(text),
+        },
+        ws::Frame::Binary(bytes) => Frame::Binary(bytes),
+        // Fragmented messages are not used by the signaling protocol
+        ws::Frame::Continuation(_) => Frame::Binary(Bytes::new()),
+        ws::Frame::Ping(bytes) => Frame::Ping(bytes),
+        ws::Frame::Pong(bytes) => Frame::Pong(bytes),
+        ws::Frame::Close(_) => Frame::Close,
+    }
+}
+
+fn to_message(frame: Frame) -> ws::Message {
+    match frame {
+        Frame::Text(text) => ws::Message::Text(text.into()),
+        Frame::Binary(bytes) => ws::Message::Binary(bytes),
+        Frame::Ping(bytes) => ws::Message::Ping(bytes),
+        Frame::Pong(bytes) => ws::Message::Pong(bytes),
+        Frame::Close => ws::Message::Close(Some(ws::CloseCode::Normal.into())),
+    }
+}
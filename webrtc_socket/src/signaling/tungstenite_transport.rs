@@ -0,0 +1,65 @@
+use futures_util::{future, SinkExt, StreamExt};
+use tokio_tungstenite::tungstenite::{
+    self,
+    client::IntoClientRequest,
+    http::{header::AUTHORIZATION, HeaderValue, StatusCode},
+    protocol::{frame::coding::CloseCode, CloseFrame},
+    Message,
+};
+
+use super::{BasicAuth, Frame, SignalingConnection};
+use crate::{error::Result, Error};
+
+pub(super) async fn connect(url: &str, auth: Option<BasicAuth<'_>>) -> Result<SignalingConnection> {
+    let mut request = url.into_client_request().map_err(connect_error)?;
+    if let Some(auth) = auth {
+        let credentials = format!("{}:{}", auth.user, auth.password.unwrap_or_default());
+        let authorization =
+            HeaderValue::from_str(&format!("Basic {}", base64::encode(credentials)))
+                .map_err(|e| Error::Protocol(e.to_string()))?;
+        request.headers_mut().insert(AUTHORIZATION, authorization);
+    }
+
+    let (ws, _res) = tokio_tungstenite::connect_async(request)
+        .await
+        .map_err(connect_error)?;
+    let ws = ws
+        .with(|frame: Frame| future::ready(Ok::<_, Error>(to_message(frame))))
+        .map(|message| message.map(from_message).map_err(Error::from));
+    Ok(Box::new(ws))
+}
+
+fn connect_error(e: tungstenite::Error) -> Error {
+    match e {
+        tungstenite::Error::Http(res) if res.status() == StatusCode::UNAUTHORIZED => {
+            Error::Unauthorized
+        }
+        tungstenite::Error::Io(e) => Error::ServerUnreachable(e.to_string()),
+        e => Error::Protocol(e.to_string()),
+    }
+}
+
+fn from_message(message: Message) -> Frame {
+    match message {
+        Message::Text(text) => Frame::Text(text),
+        Message::Binary(bytes) => Frame::Binary(bytes.into()),
+        Message::Ping(bytes) => Frame::Ping(bytes.into()),
+        Message::Pong(bytes) => Frame::Pong(bytes.into()),
+        Message::Close(_) => Frame::Close,
+        // Only produced when writing raw frames
+        Message::Frame(frame) => Frame::Binary(frame.into_data().into()),
+    }
+}
+
+fn to_message(frame: Frame) -> Message {
+    match frame {
+        Frame::Text(text) => Message::Text(text),
+        Frame::Binary(bytes) => Message::Binary(bytes.to_vec()),
+        Frame::Ping(bytes) => Message::Ping(bytes.to_vec()),
+        Frame::Pong(bytes) => Message::Pong(bytes.to_vec()),
+        Frame::Close => Message::Close(Some(CloseFrame {
+            code: CloseCode::Normal,
+            reason: "".into(),
+        })),
+    }
+}
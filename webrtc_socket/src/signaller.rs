@@ -0,0 +1,80 @@
+use std::sync::Arc;
+
+use futures_util::future::BoxFuture;
+use tokio::time;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::{
+    error::{Error, Result},
+    message::{Message, PeerMessage},
+    peer::Reconnect,
+};
+
+mod channel;
+mod lan;
+mod manual;
+mod matchbox;
+mod matchmaker;
+
+pub use channel::{ChannelHub, ChannelSignaller};
+pub use lan::{LanConfig, LanSignaller};
+pub use manual::{Bundle, BundleKind, ManualHandle, ManualSignaller};
+pub use matchbox::MatchboxSignaller;
+pub use matchmaker::MatchmakerSignaller;
+
+/// Something a [`SignalingSession`] received for the socket.
+#[derive(Debug)]
+pub enum SignalingEvent {
+    /// Peer list, a peer joining or leaving, or an offer, answer or
+    /// candidate relayed from another peer.
+    Message(Message),
+    /// Input that could not be decoded, it is counted and dropped.
+    Invalid(String),
+}
+
+/// A signaling backend [`crate::WebRTCSocket`] uses to find peers and to
+/// exchange offers, answers and candidates with them.
+pub trait Signaller {
+    /// Logs in and returns the id assigned to us. Called again with backoff
+    /// after the session was lost, the new session has to keep the id.
+    fn connect(&self) -> BoxFuture<'static, Result<(Uuid, Box<dyn SignalingSession + Send>)>>;
+}
+
+/// A logged in session of a [`Signaller`].
+pub trait SignalingSession {
+    /// Next event, `Ok(None)` once the backend ended the session on purpose.
+    /// An error means the session was lost and the socket reconnects.
+    fn recv(&mut self) -> BoxFuture<'_, Result<Option<SignalingEvent>>>;
+
+    /// Relays an offer, answer or candidate to `msg.peer_id`.
+    fn send(&mut self, msg: PeerMessage) -> BoxFuture<'_, Result<()>>;
+
+    /// Logs out, so the other peers are told right away.
+    fn close(&mut self) -> BoxFuture<'_, Result<()>>;
+}
+
+/// Connects again until it succeeds, waiting twice as long after every failed
+/// attempt. Gives up right away if the credentials are rejected.
+pub(crate) fn reconnect(
+    signaller: Arc<dyn Signaller + Send + Sync>,
+    backoff: Reconnect,
+) -> BoxFuture<'static, Result<(Uuid, Box<dyn SignalingSession + Send>)>> {
+    Box::pin(async move {
+        let mut delay = backoff.initial_delay;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            match signaller.connect().await {
+                Ok(res) => return Ok(res),
+                Err(Error::Unauthorized) => return Err(Error::Unauthorized),
+                Err(e) if backoff.max_attempts.map_or(false, |max| attempt >= max) => {
+                    return Err(e)
+                }
+                Err(e) => warn!("Reconnect attempt {attempt} failed: {e}"),
+            }
+            time::sleep(delay).await;
+            delay = (delay * 2).min(backoff.max_delay);
+        }
+    })
+}
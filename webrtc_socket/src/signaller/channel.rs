@@ -0,0 +1,155 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use futures_util::future::BoxFuture;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use uuid::Uuid;
+
+use super::{SignalingEvent, SignalingSession, Signaller};
+use crate::{
+    error::{Error, Result},
+    message::{Message, PeerMessage},
+};
+
+/// In-process signaling server, connects sockets of the same process without
+/// the matchmaker, e.g. in tests.
+#[derive(Debug, Clone, Default)]
+pub struct ChannelHub {
+    clients: Arc<Mutex<HashMap<Uuid, UnboundedSender<Message>>>>,
+}
+
+impl ChannelHub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Signaller for a new client of this hub, its id is kept across
+    /// reconnects.
+    pub fn signaller(&self) -> ChannelSignaller {
+        ChannelSignaller {
+            id: Uuid::new_v4(),
+            hub: self.clone(),
+        }
+    }
+
+    fn join(&self, id: Uuid) -> (UnboundedSender<Message>, UnboundedReceiver<Message>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut clients = self.clients.lock().unwrap();
+        let resumed = clients.contains_key(&id);
+        let ids = clients
+            .keys()
+            .filter(|&&other| other != id)
+            .copied()
+            .collect();
+        let _ = tx.send(Message::Peers { ids });
+        if !resumed {
+            for client in clients.values() {
+                let _ = client.send(Message::NewPeer { id });
+            }
+        }
+        clients.insert(id, tx.clone());
+        (tx, rx)
+    }
+
+    fn relay(&self, msg: PeerMessage) {
+        if let Some(client) = self.clients.lock().unwrap().get(&msg.peer_id) {
+            let _ = client.send(msg.content);
+        }
+    }
+
+    /// Removes the client, unless `tx` belongs to an older session that was
+    /// already replaced by a reconnect.
+    fn leave(&self, id: Uuid, tx: &UnboundedSender<Message>) {
+        let mut clients = self.clients.lock().unwrap();
+        if clients
+            .get(&id)
+            .map_or(false, |client| client.same_channel(tx))
+        {
+            clients.remove(&id);
+            for client in clients.values() {
+                let _ = client.send(Message::PeerDisconnected { id });
+            }
+        }
+    }
+}
+
+/// [`Signaller`] of a [`ChannelHub`] client.
+#[derive(Debug, Clone)]
+pub struct ChannelSignaller {
+    id: Uuid,
+    hub: ChannelHub,
+}
+
+impl ChannelSignaller {
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+}
+
+impl Signaller for ChannelSignaller {
+    fn connect(&self) -> BoxFuture<'static, Result<(Uuid, Box<dyn SignalingSession + Send>)>> {
+        let (tx, rx) = self.hub.join(self.id);
+        let session = ChannelSession {
+            id: self.id,
+            tx,
+            rx,
+            hub: self.hub.clone(),
+        };
+        Box::pin(async move {
+            Ok((
+                session.id,
+                Box::new(session) as Box<dyn SignalingSession + Send>,
+            ))
+        })
+    }
+}
+
+struct ChannelSession {
+    id: Uuid,
+    tx: UnboundedSender<Message>,
+    rx: UnboundedReceiver<Message>,
+    hub: ChannelHub,
+}
+
+impl SignalingSession for ChannelSession {
+    fn recv(&mut self) -> BoxFuture<'_, Result<Option<SignalingEvent>>> {
+        Box::pin(async move {
+            match self.rx.recv().await {
+                Some(msg) => Ok(Some(SignalingEvent::Message(msg))),
+                None => Err(Error::ConnectionLost("Hub closed".to_owned())),
+            }
+        })
+    }
+
+    fn send(&mut self, msg: PeerMessage) -> BoxFuture<'_, Result<()>> {
+        self.hub.relay(msg);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn close(&mut self) -> BoxFuture<'_, Result<()>> {
+        self.hub.leave(self.id, &self.tx);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+impl Drop for ChannelSession {
+    fn drop(&mut self) {
+        self.hub.leave(self.id, &self.tx);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        peer::RtcConfigBuilder,
+        testing::{connect, hub_sockets},
+    };
+
+    #[tokio::test]
+    async fn sockets_connect_through_hub() {
+        let mut sockets = hub_sockets::<2>(RtcConfigBuilder::new()).await;
+        connect(&mut sockets).await;
+    }
+}
@@ -0,0 +1,366 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    time::Duration,
+};
+
+use futures_util::future::BoxFuture;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::{
+    net::UdpSocket,
+    select,
+    time::{self, Instant, Interval, MissedTickBehavior},
+};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use super::{SignalingEvent, SignalingSession, Signaller};
+use crate::{
+    error::Result,
+    message::{Message, PeerMessage},
+};
+
+/// Largest datagram we expect, offers with many candidates stay well below.
+const MAX_DATAGRAM: usize = 65_507;
+
+/// Where and how often [`LanSignaller`]s announce themselves.
+#[derive(Debug, Clone)]
+pub struct LanConfig {
+    /// Only peers announcing the same session connect to each other.
+    pub session: String,
+    /// Broadcast or multicast address every peer listens on.
+    pub discovery_addr: SocketAddrV4,
+    pub announce_interval: Duration,
+    /// A peer that wasn't heard from for this long left.
+    pub peer_timeout: Duration,
+}
+
+impl Default for LanConfig {
+    fn default() -> Self {
+        Self {
+            session: "default".to_owned(),
+            discovery_addr: SocketAddrV4::new(Ipv4Addr::BROADCAST, 37657),
+            announce_interval: Duration::from_secs(1),
+            peer_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+enum LanPacket {
+    /// Sent to the discovery address, `port` receives the signals.
+    Announce {
+        session: String,
+        id: Uuid,
+        port: u16,
+    },
+    Leave {
+        session: String,
+        id: Uuid,
+    },
+    /// Sent directly to the peer.
+    Signal {
+        from: Uuid,
+        to: Uuid,
+        msg: Message,
+    },
+}
+
+/// Finds peers on the local network without a signaling server and sends
+/// them offers, answers and candidates directly.
+#[derive(Debug, Clone)]
+pub struct LanSignaller {
+    id: Uuid,
+    config: LanConfig,
+}
+
+impl LanSignaller {
+    pub fn new(config: LanConfig) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            config,
+        }
+    }
+}
+
+impl Signaller for LanSignaller {
+    fn connect(&self) -> BoxFuture<'static, Result<(Uuid, Box<dyn SignalingSession + Send>)>> {
+        let LanSignaller { id, config } = self.clone();
+        Box::pin(async move {
+            let discovery = bind_discovery(config.discovery_addr)?;
+            let signal = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+            let mut announce = time::interval(config.announce_interval);
+            announce.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            let session = LanSession {
+                id,
+                config,
+                discovery,
+                signal,
+                announce,
+                peers: Default::default(),
+                events: Default::default(),
+                discovery_buf: vec![0; MAX_DATAGRAM],
+                signal_buf: vec![0; MAX_DATAGRAM],
+            };
+            Ok((id, Box::new(session) as Box<dyn SignalingSession + Send>))
+        })
+    }
+}
+
+/// Binds the discovery port, shared with the other peers on this host.
+fn bind_discovery(addr: SocketAddrV4) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    // Needed by the BSDs to deliver broadcasts to every socket on the port
+    #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+    socket.set_reuse_port(true)?;
+    socket.set_broadcast(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, addr.port()).into())?;
+    if addr.ip().is_multicast() {
+        socket.join_multicast_v4(addr.ip(), &Ipv4Addr::UNSPECIFIED)?;
+        socket.set_multicast_loop_v4(true)?;
+    }
+    UdpSocket::from_std(socket.into())
+}
+
+struct KnownPeer {
+    /// Receives our signals.
+    addr: SocketAddr,
+    last_seen: Instant,
+}
+
+struct LanSession {
+    id: Uuid,
+    config: LanConfig,
+    discovery: UdpSocket,
+    signal: UdpSocket,
+    announce: Interval,
+    peers: HashMap<Uuid, KnownPeer>,
+    /// Messages for the socket that were not picked up yet.
+    events: VecDeque<Message>,
+    /// Kept across calls to [`LanSession::recv`], so it does not allocate.
+    discovery_buf: Vec<u8>,
+    signal_buf: Vec<u8>,
+}
+
+impl LanSession {
+    async fn broadcast(&self, packet: &LanPacket) -> Result<()> {
+        let bytes = serde_json::to_vec(packet)?;
+        self.discovery
+            .send_to(&bytes, self.config.discovery_addr)
+            .await?;
+        Ok(())
+    }
+
+    async fn announce(&mut self) -> Result<()> {
+        let packet = LanPacket::Announce {
+            session: self.config.session.clone(),
+            id: self.id,
+            port: self.signal.local_addr()?.port(),
+        };
+        self.broadcast(&packet).await?;
+
+        let timeout = self.config.peer_timeout;
+        let expired: Vec<_> = self
+            .peers
+            .iter()
+            .filter(|(_, peer)| peer.last_seen.elapsed() > timeout)
+            .map(|(&id, _)| id)
+            .collect();
+        for id in expired {
+            debug!("LAN peer {id} timed out");
+            self.peers.remove(&id);
+            self.events.push_back(Message::PeerDisconnected { id });
+        }
+        Ok(())
+    }
+
+    /// Remembers where to reach the peer. The lower id makes the offer like
+    /// a peer that was already logged in to the matchmaker.
+    fn discovered(&mut self, id: Uuid, addr: SocketAddr) {
+        let last_seen = Instant::now();
+        if let Some(peer) = self.peers.get_mut(&id) {
+            peer.addr = addr;
+            peer.last_seen = last_seen;
+            return;
+        }
+        debug!("Discovered LAN peer {id} at {addr}");
+        self.peers.insert(id, KnownPeer { addr, last_seen });
+        if self.id < id {
+            self.events.push_back(Message::NewPeer { id });
+        } else {
+            let ids = self.peers.keys().copied().collect();
+            self.events.push_back(Message::Peers { ids });
+        }
+    }
+
+    fn handle_packet(
+        &mut self,
+        packet: serde_json::Result<LanPacket>,
+        from: SocketAddr,
+    ) -> Option<SignalingEvent> {
+        let packet = match packet {
+            Ok(packet) => packet,
+            Err(e) => return Some(SignalingEvent::Invalid(e.to_string())),
+        };
+        match packet {
+            LanPacket::Announce { session, id, port } => {
+                if session == self.config.session && id != self.id {
+                    self.discovered(id, SocketAddr::new(from.ip(), port));
+                }
+            }
+            LanPacket::Leave { session, id } => {
+                if session == self.config.session && self.peers.remove(&id).is_some() {
+                    self.events.push_back(Message::PeerDisconnected { id });
+                }
+            }
+            LanPacket::Signal {
+                from: sender,
+                to,
+                msg,
+            } => {
+                if to == self.id {
+                    self.discovered(sender, from);
+                    self.events.push_back(msg);
+                }
+            }
+        }
+        None
+    }
+}
+
+impl SignalingSession for LanSession {
+    fn recv(&mut self) -> BoxFuture<'_, Result<Option<SignalingEvent>>> {
+        Box::pin(async move {
+            loop {
+                if let Some(msg) = self.events.pop_front() {
+                    return Ok(Some(SignalingEvent::Message(msg)));
+                }
+                let invalid = select! {
+                    res = self.discovery.recv_from(&mut self.discovery_buf) => {
+                        let (len, from) = res?;
+                        let packet = serde_json::from_slice(&self.discovery_buf[..len]);
+                        self.handle_packet(packet, from)
+                    }
+                    res = self.signal.recv_from(&mut self.signal_buf) => {
+                        let (len, from) = res?;
+                        let packet = serde_json::from_slice(&self.signal_buf[..len]);
+                        self.handle_packet(packet, from)
+                    }
+                    _ = self.announce.tick() => {
+                        self.announce().await?;
+                        None
+                    }
+                };
+                if invalid.is_some() {
+                    return Ok(invalid);
+                }
+            }
+        })
+    }
+
+    fn send(&mut self, msg: PeerMessage) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let addr = match self.peers.get(&msg.peer_id) {
+                Some(peer) => peer.addr,
+                None => {
+                    warn!("Dropping signal for unknown LAN peer {}", msg.peer_id);
+                    return Ok(());
+                }
+            };
+            let packet = LanPacket::Signal {
+                from: self.id,
+                to: msg.peer_id,
+                msg: msg.content,
+            };
+            let bytes = serde_json::to_vec(&packet)?;
+            self.signal.send_to(&bytes, addr).await?;
+            Ok(())
+        })
+    }
+
+    fn close(&mut self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let packet = LanPacket::Leave {
+                session: self.config.session.clone(),
+                id: self.id,
+            };
+            self.broadcast(&packet).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{Ipv4Addr, SocketAddrV4, UdpSocket},
+        time::Duration,
+    };
+
+    use tokio::{select, time};
+    use uuid::Uuid;
+
+    use super::{LanConfig, LanSignaller};
+    use crate::{peer::RtcConfigBuilder, EventStream, SocketEvent, WebRTCSocket};
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    async fn wait_for(events: &mut EventStream, expected: SocketEvent) {
+        while let Some(event) = events.recv().await {
+            if event == expected {
+                return;
+            }
+        }
+    }
+
+    /// Broadcast on the loopback interface, on a port no other test uses.
+    fn loopback_config() -> LanConfig {
+        let port = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        LanConfig {
+            session: Uuid::new_v4().to_string(),
+            discovery_addr: SocketAddrV4::new(Ipv4Addr::new(127, 255, 255, 255), port),
+            announce_interval: Duration::from_millis(100),
+            peer_timeout: Duration::from_millis(500),
+        }
+    }
+
+    #[tokio::test]
+    async fn sockets_discover_each_other_on_loopback() {
+        let lan = loopback_config();
+        let config = RtcConfigBuilder::new().ice_servers(vec![]).build();
+        let mut alice =
+            WebRTCSocket::with_signaller(config.clone(), LanSignaller::new(lan.clone()))
+                .await
+                .unwrap();
+        let mut bob = WebRTCSocket::with_signaller(config, LanSignaller::new(lan))
+            .await
+            .unwrap();
+        let (alice_id, bob_id) = (alice.id(), bob.id());
+        let mut alice_events = alice.subscribe();
+        let mut bob_events = bob.subscribe();
+
+        let ready = async {
+            wait_for(&mut alice_events, SocketEvent::PeerJoined { id: bob_id }).await;
+            wait_for(&mut alice_events, SocketEvent::PeerReady { id: bob_id }).await;
+            wait_for(&mut bob_events, SocketEvent::PeerReady { id: alice_id }).await;
+        };
+        select! {
+            res = alice.run() => panic!("alice stopped: {res:?}"),
+            res = bob.run() => panic!("bob stopped: {res:?}"),
+            res = time::timeout(TIMEOUT, ready) => res.unwrap(),
+        }
+
+        bob.close().await.unwrap();
+        let left = wait_for(&mut alice_events, SocketEvent::PeerLeft { id: bob_id });
+        select! {
+            res = alice.run() => panic!("alice stopped: {res:?}"),
+            res = time::timeout(TIMEOUT, left) => res.unwrap(),
+        }
+    }
+}
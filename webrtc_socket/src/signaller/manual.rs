@@ -0,0 +1,361 @@
+use std::{
+    collections::HashMap,
+    future,
+    sync::{Arc, Mutex},
+};
+
+use futures_util::future::BoxFuture;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use uuid::Uuid;
+use webrtc::{
+    ice_transport::ice_candidate::RTCIceCandidateInit,
+    peer_connection::sdp::{sdp_type::RTCSdpType, session_description::RTCSessionDescription},
+};
+
+use super::{SignalingEvent, SignalingSession, Signaller};
+use crate::{
+    error::{Error, Result},
+    message::{Message, PeerMessage},
+};
+
+/// Whether a [`Bundle`] starts a handshake or completes it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum BundleKind {
+    Offer,
+    Answer,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+struct Candidate {
+    candidate: String,
+    sdp_mid: Option<String>,
+    sdp_mline_index: Option<u16>,
+}
+
+/// Offer or answer with all candidates of the sender, so nothing has to be
+/// trickled afterwards.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Bundle {
+    pub from: Uuid,
+    pub to: Uuid,
+    pub kind: BundleKind,
+    sdp: String,
+    candidates: Vec<Candidate>,
+}
+
+impl Bundle {
+    /// Compact text to copy and paste, URL safe base64.
+    pub fn encode(&self) -> String {
+        base64::encode_config(bincode::serialize(self).unwrap(), base64::URL_SAFE_NO_PAD)
+    }
+
+    pub fn decode(text: &str) -> Result<Self> {
+        let bytes = base64::decode_config(text.trim(), base64::URL_SAFE_NO_PAD)
+            .map_err(|e| Error::InvalidBundle(e.to_string()))?;
+        bincode::deserialize(&bytes).map_err(|e| Error::InvalidBundle(e.to_string()))
+    }
+
+    /// What the sender would have trickled over a signaling server.
+    fn into_messages(self) -> Result<Vec<Message>> {
+        let id = self.from;
+        let sdp_type = match self.kind {
+            BundleKind::Offer => RTCSdpType::Offer,
+            BundleKind::Answer => RTCSdpType::Answer,
+        };
+        // Session descriptions are only public through serde
+        let description: RTCSessionDescription =
+            serde_json::from_value(serde_json::json!({ "type": sdp_type, "sdp": self.sdp }))?;
+        let mut messages = vec![match self.kind {
+            BundleKind::Offer => Message::Offer {
+                id,
+                offer: description,
+            },
+            BundleKind::Answer => Message::Answer {
+                id,
+                answer: description,
+            },
+        }];
+        messages.extend(self.candidates.into_iter().map(|c| Message::IceCandidate {
+            id,
+            candidate: RTCIceCandidateInit {
+                candidate: c.candidate,
+                sdp_mid: c.sdp_mid,
+                sdp_mline_index: c.sdp_mline_index,
+                ..Default::default()
+            },
+        }));
+        messages.push(Message::EndOfCandidates { id });
+        Ok(messages)
+    }
+}
+
+/// A bundle that is still gathering candidates.
+#[derive(Default)]
+struct Pending {
+    description: Option<(BundleKind, String)>,
+    candidates: Vec<Candidate>,
+    gathered: bool,
+}
+
+/// Connects two sockets without a signaling server, the [`Bundle`]s are
+/// exchanged out of band through a [`ManualHandle`].
+///
+/// The host creates an offer for each guest, a guest joins with that offer and
+/// answers it. Guests only connect to the host.
+#[derive(Debug)]
+pub struct ManualSignaller {
+    id: Uuid,
+    inbox_rx: Mutex<Option<UnboundedReceiver<Message>>>,
+    bundles_tx: UnboundedSender<Bundle>,
+}
+
+impl ManualSignaller {
+    /// Signaller of the socket that creates the offers.
+    pub fn host() -> (ManualSignaller, ManualHandle) {
+        ManualSignaller::with_id(Uuid::new_v4())
+    }
+
+    /// Signaller of a guest, it takes the id the host assigned in `offer`.
+    pub fn join(offer: &str) -> Result<(ManualSignaller, ManualHandle)> {
+        let offer = Bundle::decode(offer)?;
+        if offer.kind != BundleKind::Offer {
+            return Err(Error::InvalidBundle("expected an offer".to_owned()));
+        }
+        let (signaller, handle) = ManualSignaller::with_id(offer.to);
+        let _ = handle.inbox_tx.send(Message::Peers {
+            ids: vec![offer.from],
+        });
+        handle.import_bundle(offer)?;
+        Ok((signaller, handle))
+    }
+
+    fn with_id(id: Uuid) -> (ManualSignaller, ManualHandle) {
+        let (inbox_tx, inbox_rx) = mpsc::unbounded_channel();
+        let (bundles_tx, bundles_rx) = mpsc::unbounded_channel();
+        let signaller = ManualSignaller {
+            id,
+            inbox_rx: Mutex::new(Some(inbox_rx)),
+            bundles_tx,
+        };
+        let handle = ManualHandle {
+            id,
+            inbox_tx,
+            bundles_rx,
+        };
+        (signaller, handle)
+    }
+}
+
+impl Signaller for ManualSignaller {
+    fn connect(&self) -> BoxFuture<'static, Result<(Uuid, Box<dyn SignalingSession + Send>)>> {
+        let id = self.id;
+        let inbox_rx = self.inbox_rx.lock().unwrap().take();
+        let bundles_tx = self.bundles_tx.clone();
+        Box::pin(async move {
+            let inbox_rx = inbox_rx.ok_or_else(|| {
+                Error::Protocol("Manual signaling can only connect once".to_owned())
+            })?;
+            let session = ManualSession {
+                id,
+                inbox_rx,
+                bundles_tx,
+                pending: Default::default(),
+            };
+            Ok((id, Box::new(session) as Box<dyn SignalingSession + Send>))
+        })
+    }
+}
+
+/// Creates, hands out and imports the [`Bundle`]s of a [`ManualSignaller`]
+/// while [`crate::WebRTCSocket::run`] is running.
+#[derive(Debug)]
+pub struct ManualHandle {
+    id: Uuid,
+    inbox_tx: UnboundedSender<Message>,
+    bundles_rx: UnboundedReceiver<Bundle>,
+}
+
+impl ManualHandle {
+    /// Our id, the guest's id is taken from the host's offer.
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    /// Invites a new guest and waits until the offer for it gathered all
+    /// candidates.
+    pub async fn create_offer(&mut self) -> Result<String> {
+        let guest = Uuid::new_v4();
+        self.inbox_tx
+            .send(Message::NewPeer { id: guest })
+            .map_err(|_| Error::ChannelClosed)?;
+        self.next_bundle().await
+    }
+
+    /// Next offer or answer to hand to the other side, e.g. the guest's answer
+    /// or a renegotiation.
+    pub async fn next_bundle(&mut self) -> Result<String> {
+        let bundle = self.bundles_rx.recv().await.ok_or(Error::ChannelClosed)?;
+        Ok(bundle.encode())
+    }
+
+    /// Imports a bundle the other side created for us.
+    pub fn import(&self, bundle: &str) -> Result<()> {
+        self.import_bundle(Bundle::decode(bundle)?)
+    }
+
+    fn import_bundle(&self, bundle: Bundle) -> Result<()> {
+        if bundle.to != self.id {
+            return Err(Error::InvalidBundle(format!(
+                "bundle is for {} instead of {}",
+                bundle.to, self.id
+            )));
+        }
+        for msg in bundle.into_messages()? {
+            self.inbox_tx.send(msg).map_err(|_| Error::ChannelClosed)?;
+        }
+        Ok(())
+    }
+}
+
+struct ManualSession {
+    id: Uuid,
+    inbox_rx: UnboundedReceiver<Message>,
+    bundles_tx: UnboundedSender<Bundle>,
+    pending: HashMap<Uuid, Pending>,
+}
+
+impl ManualSession {
+    /// Hands out the bundle for `to` once it has a description and all
+    /// candidates, they may arrive in any order.
+    fn flush(&mut self, to: Uuid) {
+        let complete = self
+            .pending
+            .get(&to)
+            .map_or(false, |p| p.gathered && p.description.is_some());
+        if !complete {
+            return;
+        }
+        let pending = self.pending.remove(&to).unwrap();
+        let (kind, sdp) = pending.description.unwrap();
+        let _ = self.bundles_tx.send(Bundle {
+            from: self.id,
+            to,
+            kind,
+            sdp,
+            candidates: pending.candidates,
+        });
+    }
+}
+
+impl SignalingSession for ManualSession {
+    fn recv(&mut self) -> BoxFuture<'_, Result<Option<SignalingEvent>>> {
+        Box::pin(async move {
+            match self.inbox_rx.recv().await {
+                Some(msg) => Ok(Some(SignalingEvent::Message(msg))),
+                // Without a handle there is nothing left to import
+                None => future::pending().await,
+            }
+        })
+    }
+
+    fn send(&mut self, msg: PeerMessage) -> BoxFuture<'_, Result<()>> {
+        let to = msg.peer_id;
+        let pending = self.pending.entry(to).or_default();
+        match msg.content {
+            Message::Offer { offer, .. } => {
+                pending.description = Some((BundleKind::Offer, offer.sdp));
+            }
+            Message::Answer { answer, .. } => {
+                pending.description = Some((BundleKind::Answer, answer.sdp));
+            }
+            Message::IceCandidate { candidate, .. } => pending.candidates.push(Candidate {
+                candidate: candidate.candidate,
+                sdp_mid: candidate.sdp_mid,
+                sdp_mline_index: candidate.sdp_mline_index,
+            }),
+            Message::EndOfCandidates { .. } => pending.gathered = true,
+            _ => {}
+        }
+        self.flush(to);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn close(&mut self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::{select, time};
+    use uuid::Uuid;
+
+    use super::{Bundle, BundleKind, Candidate, ManualSignaller};
+    use crate::{peer::RtcConfigBuilder, EventStream, SocketEvent, WebRTCSocket};
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    async fn wait_for_ready(events: &mut EventStream, id: Uuid) {
+        while let Some(event) = events.recv().await {
+            if event == (SocketEvent::PeerReady { id }) {
+                return;
+            }
+        }
+    }
+
+    #[test]
+    fn bundles_round_trip() {
+        let bundle = Bundle {
+            from: Uuid::new_v4(),
+            to: Uuid::new_v4(),
+            kind: BundleKind::Answer,
+            sdp: "v=0\r\no=- 0 0 IN IP4 0.0.0.0\r\n".to_owned(),
+            candidates: vec![Candidate {
+                candidate: "candidate:1 1 udp 2130706431 127.0.0.1 5000 typ host".to_owned(),
+                sdp_mid: Some("0".to_owned()),
+                sdp_mline_index: Some(0),
+            }],
+        };
+        let text = bundle.encode();
+        assert!(text
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+        assert_eq!(Bundle::decode(&text).unwrap(), bundle);
+        assert!(Bundle::decode("not a bundle").is_err());
+    }
+
+    #[tokio::test]
+    async fn sockets_connect_with_manual_bundles() {
+        let config = RtcConfigBuilder::new().ice_servers(vec![]).build();
+        let (signaller, mut host_handle) = ManualSignaller::host();
+        let mut host = WebRTCSocket::with_signaller(config.clone(), signaller)
+            .await
+            .unwrap();
+        let mut host_events = host.subscribe();
+
+        let exchange = async {
+            let offer = host_handle.create_offer().await.unwrap();
+            let (signaller, mut guest_handle) = ManualSignaller::join(&offer).unwrap();
+            let mut guest = WebRTCSocket::with_signaller(config, signaller)
+                .await
+                .unwrap();
+            let guest_id = guest.id();
+            let mut guest_events = guest.subscribe();
+            select! {
+                res = guest.run() => panic!("guest stopped: {res:?}"),
+                _ = async {
+                    let answer = guest_handle.next_bundle().await.unwrap();
+                    host_handle.import(&answer).unwrap();
+                    wait_for_ready(&mut host_events, guest_id).await;
+                    wait_for_ready(&mut guest_events, host_handle.id()).await;
+                } => {}
+            }
+        };
+        select! {
+            res = host.run() => panic!("host stopped: {res:?}"),
+            res = time::timeout(TIMEOUT, exchange) => res.unwrap(),
+        }
+    }
+}
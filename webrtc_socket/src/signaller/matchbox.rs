@@ -0,0 +1,157 @@
+use std::time::Duration;
+
+use futures_util::{future::BoxFuture, SinkExt as _, StreamExt as _};
+use tokio::time::{self, Instant};
+use uuid::Uuid;
+
+use super::{SignalingEvent, SignalingSession, Signaller};
+use crate::{
+    error::{Error, Result},
+    matchbox::{PeerEvent, PeerRequest, PeerSignal},
+    message::{Message, PeerMessage},
+    signaling::{self, Frame, SignalingClient, SignalingConnection},
+};
+
+/// How often an idle session sends [`PeerRequest::KeepAlive`], same as
+/// matchbox_socket.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Joins a room of a [matchbox_server](https://github.com/johanhelsing/matchbox)
+/// or of the matchmaker's matchbox route.
+///
+/// matchbox assigns a new id on every login, so the socket can't resume a lost
+/// session and stops.
+#[derive(Debug, Clone)]
+pub struct MatchboxSignaller {
+    room_url: String,
+    client: SignalingClient,
+}
+
+impl MatchboxSignaller {
+    /// `room_url` like `ws://127.0.0.1:3536/my_room`.
+    pub fn new<S: AsRef<str>>(room_url: S) -> Self {
+        Self {
+            room_url: room_url.as_ref().to_string(),
+            client: Default::default(),
+        }
+    }
+
+    pub fn signaling_client(mut self, client: SignalingClient) -> Self {
+        self.client = client;
+        self
+    }
+}
+
+impl Signaller for MatchboxSignaller {
+    fn connect(&self) -> BoxFuture<'static, Result<(Uuid, Box<dyn SignalingSession + Send>)>> {
+        let signaller = self.clone();
+        Box::pin(async move {
+            let mut ws = signaling::connect_url(signaller.client, &signaller.room_url).await?;
+            let id = match ws.next().await {
+                Some(Ok(Frame::Text(text))) => match serde_json::from_str(&text)? {
+                    PeerEvent::IdAssigned(id) => id,
+                    _ => {
+                        return Err(Error::Protocol(
+                            "First event must be IdAssigned!".to_owned(),
+                        ))
+                    }
+                },
+                _ => {
+                    return Err(Error::ConnectionLost(
+                        "Error with Ws connection!".to_owned(),
+                    ))
+                }
+            };
+            let session = MatchboxSession {
+                ws,
+                keep_alive: Instant::now() + KEEP_ALIVE_INTERVAL,
+            };
+            Ok((id, Box::new(session) as Box<dyn SignalingSession + Send>))
+        })
+    }
+}
+
+struct MatchboxSession {
+    ws: SignalingConnection,
+    /// When to send the next [`PeerRequest::KeepAlive`].
+    keep_alive: Instant,
+}
+
+impl MatchboxSession {
+    async fn request(&mut self, request: PeerRequest) -> Result<()> {
+        self.keep_alive = Instant::now() + KEEP_ALIVE_INTERVAL;
+        let text = serde_json::to_string(&request)?;
+        self.ws.send(Frame::Text(text)).await
+    }
+}
+
+/// The socket's view of a matchbox event.
+fn to_event(event: PeerEvent) -> SignalingEvent {
+    let msg = match event {
+        PeerEvent::NewPeer(id) => Message::NewPeer { id },
+        PeerEvent::PeerLeft(id) => Message::PeerDisconnected { id },
+        PeerEvent::Signal { sender, data } => match data.into_message(sender) {
+            Ok(msg) => msg,
+            Err(e) => return SignalingEvent::Invalid(e.to_string()),
+        },
+        PeerEvent::IdAssigned(_) => return SignalingEvent::Invalid("id assigned twice".to_owned()),
+    };
+    SignalingEvent::Message(msg)
+}
+
+impl SignalingSession for MatchboxSession {
+    fn recv(&mut self) -> BoxFuture<'_, Result<Option<SignalingEvent>>> {
+        Box::pin(async move {
+            loop {
+                let frame = match time::timeout_at(self.keep_alive, self.ws.next()).await {
+                    Ok(Some(frame)) => frame?,
+                    Ok(None) => {
+                        return Err(Error::ConnectionLost(
+                            "Signaling connection closed unexpectedly".to_owned(),
+                        ))
+                    }
+                    Err(_) => {
+                        self.request(PeerRequest::KeepAlive).await?;
+                        continue;
+                    }
+                };
+                match frame {
+                    Frame::Text(text) => {
+                        return Ok(Some(match serde_json::from_str(&text) {
+                            Ok(event) => to_event(event),
+                            Err(e) => SignalingEvent::Invalid(e.to_string()),
+                        }))
+                    }
+                    Frame::Close => {
+                        self.ws.close().await?;
+                        return Ok(None);
+                    }
+                    Frame::Ping(msg) => self.ws.send(Frame::Pong(msg)).await?,
+                    Frame::Pong(_) => {}
+                    Frame::Binary(_) => {
+                        return Ok(Some(SignalingEvent::Invalid(
+                            "unexpected binary frame".to_owned(),
+                        )))
+                    }
+                }
+            }
+        })
+    }
+
+    fn send(&mut self, msg: PeerMessage) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            // matchbox has no end of candidates, peers just stop trickling
+            match PeerSignal::from_message(msg.content)? {
+                Some((_, data)) => {
+                    let receiver = msg.peer_id;
+                    self.request(PeerRequest::Signal { receiver, data }).await
+                }
+                None => Ok(()),
+            }
+        })
+    }
+
+    fn close(&mut self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move { self.ws.send(Frame::Close).await })
+    }
+}
@@ -0,0 +1,110 @@
+use futures_util::{future::BoxFuture, SinkExt as _, StreamExt as _};
+use uuid::Uuid;
+
+use super::{SignalingEvent, SignalingSession, Signaller};
+use crate::{
+    error::{Error, Result},
+    message::{Message, PeerMessage},
+    peer::RtcConfig,
+    signaling::{self, Frame, SignalingConnection},
+};
+
+/// The matchmaker's JSON [`Message`] protocol on `/ws/login`, the default
+/// [`Signaller`].
+#[derive(Debug, Clone)]
+pub struct MatchmakerSignaller {
+    rtc_config: RtcConfig,
+}
+
+impl MatchmakerSignaller {
+    pub fn new(rtc_config: RtcConfig) -> Self {
+        Self { rtc_config }
+    }
+}
+
+impl Signaller for MatchmakerSignaller {
+    fn connect(&self) -> BoxFuture<'static, Result<(Uuid, Box<dyn SignalingSession + Send>)>> {
+        let rtc_config = self.rtc_config.clone();
+        Box::pin(async move {
+            let (id, ws) = login(&rtc_config).await?;
+            Ok((
+                id,
+                Box::new(MatchmakerSession { ws }) as Box<dyn SignalingSession + Send>,
+            ))
+        })
+    }
+}
+
+/// Connects to the signaling server and waits for the id it assigns to us.
+async fn login(rtc_config: &RtcConfig) -> Result<(Uuid, SignalingConnection)> {
+    let mut ws = signaling::connect(rtc_config).await?;
+    let id = if let Some(Ok(Frame::Text(msg))) = ws.next().await {
+        let msg = Message::from_json(msg.as_bytes())?;
+        if let Message::Id(id) = msg {
+            id
+        } else {
+            return Err(Error::Protocol("First message must be Id!".to_owned()));
+        }
+    } else {
+        return Err(Error::ConnectionLost(
+            "Error with Ws connection!".to_owned(),
+        ));
+    };
+    Ok((id, ws))
+}
+
+struct MatchmakerSession {
+    ws: SignalingConnection,
+}
+
+impl SignalingSession for MatchmakerSession {
+    fn recv(&mut self) -> BoxFuture<'_, Result<Option<SignalingEvent>>> {
+        Box::pin(async move {
+            loop {
+                let frame = match self.ws.next().await {
+                    Some(frame) => frame?,
+                    None => {
+                        return Err(Error::ConnectionLost(
+                            "Signaling connection closed unexpectedly".to_owned(),
+                        ))
+                    }
+                };
+                match frame {
+                    Frame::Text(msg) => {
+                        return Ok(Some(match Message::from_json(msg.as_bytes()) {
+                            Ok(msg) => SignalingEvent::Message(msg),
+                            Err(e) => SignalingEvent::Invalid(e.to_string()),
+                        }))
+                    }
+                    // The server closes on restarts and proxies on timeouts,
+                    // the socket reconnects like after any other loss
+                    Frame::Close => {
+                        let _ = self.ws.close().await;
+                        return Err(Error::ConnectionLost(
+                            "Signaling server closed the connection".to_owned(),
+                        ));
+                    }
+                    // The server drops clients that stop answering its pings
+                    Frame::Ping(msg) => self.ws.send(Frame::Pong(msg)).await?,
+                    Frame::Pong(_) => {}
+                    Frame::Binary(_) => {
+                        return Ok(Some(SignalingEvent::Invalid(
+                            "unexpected binary frame".to_owned(),
+                        )))
+                    }
+                }
+            }
+        })
+    }
+
+    fn send(&mut self, msg: PeerMessage) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let text = serde_json::to_string(&msg)?;
+            self.ws.send(Frame::Text(text)).await
+        })
+    }
+
+    fn close(&mut self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move { self.ws.send(Frame::Close).await })
+    }
+}
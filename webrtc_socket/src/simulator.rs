@@ -0,0 +1,368 @@
+//! Impairs the GGRS traffic of a socket like a bad network would, to tune
+//! rollback settings before players run into it.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use ggrs::{Message, NonBlockingSocket};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use uuid::Uuid;
+
+use crate::wire;
+
+/// How the link to a peer misbehaves, the default is a perfect link.
+/// Chances are between 0 and 1.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct LinkConditions {
+    /// Added to every message.
+    pub latency: Duration,
+    /// Random extra delay up to this, messages may overtake each other.
+    pub jitter: Duration,
+    /// Chance that a message is dropped.
+    pub loss: f64,
+    /// Chance that a message is sent twice.
+    pub duplication: f64,
+    /// Chance that a message skips the latency and overtakes earlier ones.
+    pub reordering: f64,
+    /// Bytes per second, messages queue up behind each other above it.
+    pub bandwidth: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+struct Settings {
+    default: LinkConditions,
+    peers: HashMap<Uuid, LinkConditions>,
+}
+
+/// Changes the conditions of a [`NetworkSimulator`] while a session owns it.
+#[derive(Debug, Clone, Default)]
+pub struct SimulatorHandle {
+    settings: Arc<Mutex<Settings>>,
+}
+
+impl SimulatorHandle {
+    /// Conditions for peers without their own.
+    pub fn set_default(&self, conditions: LinkConditions) {
+        self.settings.lock().unwrap().default = conditions;
+    }
+
+    pub fn set(&self, peer: Uuid, conditions: LinkConditions) {
+        self.settings.lock().unwrap().peers.insert(peer, conditions);
+    }
+
+    /// The peer gets the default conditions again.
+    pub fn reset(&self, peer: Uuid) {
+        self.settings.lock().unwrap().peers.remove(&peer);
+    }
+
+    pub fn conditions(&self, peer: Uuid) -> LinkConditions {
+        let settings = self.settings.lock().unwrap();
+        settings
+            .peers
+            .get(&peer)
+            .unwrap_or(&settings.default)
+            .clone()
+    }
+}
+
+/// Wraps a [`GgrsSocket`](crate::GgrsSocket), a loopback socket or any other
+/// [`NonBlockingSocket`] and holds back what is sent according to the
+/// [`LinkConditions`] of the receiver.
+///
+/// Only outgoing messages are impaired, wrap the sockets of both peers to
+/// impair both directions. Queued messages go out whenever GGRS sends or
+/// polls, so delays are as precise as the session is polled.
+#[derive(Debug)]
+pub struct NetworkSimulator<S> {
+    inner: S,
+    handle: SimulatorHandle,
+    rng: StdRng,
+    /// Ordered by due time, then by when they were sent.
+    queue: BTreeMap<(Instant, u64), (Uuid, Message)>,
+    sent: u64,
+    /// When the link to a peer is done with its backlog.
+    link_free: HashMap<Uuid, Instant>,
+}
+
+impl<S: NonBlockingSocket<Uuid>> NetworkSimulator<S> {
+    /// The same seed makes the same decisions for the same traffic.
+    pub fn new(inner: S, seed: u64) -> Self {
+        Self {
+            inner,
+            handle: Default::default(),
+            rng: StdRng::seed_from_u64(seed),
+            queue: Default::default(),
+            sent: 0,
+            link_free: Default::default(),
+        }
+    }
+
+    pub fn handle(&self) -> SimulatorHandle {
+        self.handle.clone()
+    }
+
+    /// Messages that are held back.
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Drops the messages that are still held back.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn chance(&mut self, p: f64) -> bool {
+        self.rng.gen_bool(p.clamp(0.0, 1.0))
+    }
+
+    fn schedule(&mut self, msg: &Message, to: Uuid, now: Instant) {
+        let conditions = self.handle.conditions(to);
+        if self.chance(conditions.loss) {
+            return;
+        }
+        let copies = if self.chance(conditions.duplication) {
+            2
+        } else {
+            1
+        };
+        for _ in 0..copies {
+            let mut sent = now;
+            if let Some(bandwidth) = conditions.bandwidth {
+                let size = wire::serialize(msg).map_or(0, |bytes| bytes.len());
+                let free = self.link_free.get(&to).map_or(now, |&free| free.max(now));
+                sent = free + Duration::from_secs_f64(size as f64 / bandwidth.max(1) as f64);
+                self.link_free.insert(to, sent);
+            }
+            let due = if self.chance(conditions.reordering) {
+                sent
+            } else {
+                let jitter = self.rng.gen_range(Duration::ZERO..=conditions.jitter);
+                sent + conditions.latency + jitter
+            };
+            self.queue.insert((due, self.sent), (to, msg.clone()));
+            self.sent += 1;
+        }
+    }
+
+    /// Hands everything that is due to the inner socket.
+    fn flush(&mut self, now: Instant) {
+        while let Some(&key) = self.queue.keys().next() {
+            if key.0 > now {
+                break;
+            }
+            let (to, msg) = self.queue.remove(&key).unwrap();
+            self.inner.send_to(&msg, &to);
+        }
+    }
+}
+
+impl<S: NonBlockingSocket<Uuid>> NonBlockingSocket<Uuid> for NetworkSimulator<S> {
+    fn send_to(&mut self, msg: &Message, addr: &Uuid) {
+        let now = Instant::now();
+        self.schedule(msg, *addr, now);
+        self.flush(now);
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(Uuid, Message)> {
+        self.flush(Instant::now());
+        self.inner.receive_all_messages()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use ggrs::{Message, NonBlockingSocket, PlayerType, SessionBuilder, SessionState};
+    use uuid::Uuid;
+
+    use super::{LinkConditions, NetworkSimulator};
+    use crate::loopback::LoopbackNetwork;
+
+    struct TestConfig;
+
+    impl ggrs::Config for TestConfig {
+        type Input = u8;
+        type State = u8;
+        type Address = Uuid;
+    }
+
+    /// Remembers what was handed to it.
+    #[derive(Clone, Default)]
+    struct Recorder {
+        sent: Arc<Mutex<Vec<(Uuid, Message)>>>,
+    }
+
+    impl Recorder {
+        fn take(&self) -> Vec<(Uuid, Message)> {
+            std::mem::take(&mut *self.sent.lock().unwrap())
+        }
+    }
+
+    impl NonBlockingSocket<Uuid> for Recorder {
+        fn send_to(&mut self, msg: &Message, addr: &Uuid) {
+            self.sent.lock().unwrap().push((*addr, msg.clone()));
+        }
+
+        fn receive_all_messages(&mut self) -> Vec<(Uuid, Message)> {
+            vec![]
+        }
+    }
+
+    const PEER: Uuid = Uuid::from_u128(2);
+
+    /// Messages can't be built outside of GGRS, so take a sync request.
+    fn ggrs_message() -> Message {
+        let recorder = Recorder::default();
+        let mut session = SessionBuilder::<TestConfig>::new()
+            .with_num_players(2)
+            .add_player(PlayerType::Local, 0)
+            .unwrap()
+            .add_player(PlayerType::Remote(PEER), 1)
+            .unwrap()
+            .start_p2p_session(recorder.clone())
+            .unwrap();
+        session.poll_remote_clients();
+        recorder.take().pop().unwrap().1
+    }
+
+    fn simulator(seed: u64, conditions: LinkConditions) -> (NetworkSimulator<Recorder>, Recorder) {
+        let recorder = Recorder::default();
+        let simulator = NetworkSimulator::new(recorder.clone(), seed);
+        simulator.handle().set_default(conditions);
+        (simulator, recorder)
+    }
+
+    #[test]
+    fn latency_and_jitter_delay_messages() {
+        let msg = ggrs_message();
+        let (mut simulator, recorder) = simulator(
+            1,
+            LinkConditions {
+                latency: Duration::from_millis(50),
+                jitter: Duration::from_millis(10),
+                ..Default::default()
+            },
+        );
+        let now = Instant::now();
+        simulator.schedule(&msg, PEER, now);
+        simulator.flush(now + Duration::from_millis(49));
+        assert!(recorder.take().is_empty());
+        simulator.flush(now + Duration::from_millis(60));
+        assert_eq!(recorder.take().len(), 1);
+
+        simulator.handle().set(
+            PEER,
+            LinkConditions {
+                reordering: 1.0,
+                ..simulator.handle().conditions(PEER)
+            },
+        );
+        simulator.schedule(&msg, PEER, now);
+        simulator.flush(now);
+        assert_eq!(recorder.take().len(), 1);
+    }
+
+    #[test]
+    fn same_seed_makes_same_decisions() {
+        let msg = ggrs_message();
+        let conditions = LinkConditions {
+            latency: Duration::from_millis(20),
+            jitter: Duration::from_millis(30),
+            loss: 0.25,
+            duplication: 0.1,
+            ..Default::default()
+        };
+        let now = Instant::now();
+        let schedule = |seed| {
+            let (mut simulator, _) = simulator(seed, conditions.clone());
+            for _ in 0..1000 {
+                simulator.schedule(&msg, PEER, now);
+            }
+            simulator.queue.keys().copied().collect::<Vec<_>>()
+        };
+        let first = schedule(7);
+        assert_eq!(first, schedule(7));
+        assert_ne!(first, schedule(8));
+        // 750 expected after loss, plus a tenth of them twice
+        assert!((750..900).contains(&first.len()), "{}", first.len());
+    }
+
+    #[test]
+    fn bandwidth_spaces_messages() {
+        let msg = ggrs_message();
+        let size = crate::wire::serialize(&msg).unwrap().len() as u64;
+        let (mut simulator, recorder) = simulator(
+            1,
+            LinkConditions {
+                bandwidth: Some(size * 10),
+                ..Default::default()
+            },
+        );
+        let now = Instant::now();
+        for _ in 0..10 {
+            simulator.schedule(&msg, PEER, now);
+        }
+        simulator.flush(now + Duration::from_millis(450));
+        assert_eq!(recorder.take().len(), 4);
+        simulator.flush(now + Duration::from_millis(1050));
+        assert_eq!(recorder.take().len(), 6);
+    }
+
+    #[test]
+    fn conditions_change_at_runtime() {
+        let (mut network, mut sockets) = LoopbackNetwork::with_peers(2);
+        let mut handles = vec![];
+        let mut sessions: Vec<_> = sockets
+            .iter_mut()
+            .enumerate()
+            .map(|(seed, socket)| {
+                let ggrs = socket.ggrs_socket().unwrap();
+                let mut builder = SessionBuilder::<TestConfig>::new().with_num_players(2);
+                for (handle, player) in ggrs.players_snapshot().into_iter().enumerate() {
+                    builder = builder.add_player(player, handle).unwrap();
+                }
+                let simulator = NetworkSimulator::new(ggrs, seed as u64);
+                simulator.handle().set_default(LinkConditions {
+                    loss: 1.0,
+                    ..Default::default()
+                });
+                handles.push(simulator.handle());
+                builder.start_p2p_session(simulator).unwrap()
+            })
+            .collect();
+
+        let mut poll = |rounds| {
+            for _ in 0..rounds {
+                for session in sessions.iter_mut() {
+                    session.poll_remote_clients();
+                }
+                network.deliver();
+                if sessions
+                    .iter()
+                    .all(|s| s.current_state() == SessionState::Running)
+                {
+                    return true;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            false
+        };
+        assert!(!poll(50));
+        for handle in handles.iter() {
+            handle.set_default(LinkConditions::default());
+        }
+        assert!(poll(200));
+    }
+}
@@ -0,0 +1,140 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use webrtc::ice::candidate::CandidateType as IceCandidateType;
+
+/// How often a running socket refreshes the stats it hands out.
+pub const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Kind of the local candidate of the selected candidate pair.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CandidateType {
+    /// A direct connection.
+    Host,
+    /// Through a NAT, the address was learned from a STUN server.
+    ServerReflexive,
+    /// Through a NAT, the address was learned from the peer.
+    PeerReflexive,
+    /// Everything passes through a TURN server.
+    Relay,
+}
+
+impl CandidateType {
+    pub(crate) fn from_ice(candidate_type: IceCandidateType) -> Option<Self> {
+        match candidate_type {
+            IceCandidateType::Host => Some(CandidateType::Host),
+            IceCandidateType::ServerReflexive => Some(CandidateType::ServerReflexive),
+            IceCandidateType::PeerReflexive => Some(CandidateType::PeerReflexive),
+            IceCandidateType::Relay => Some(CandidateType::Relay),
+            IceCandidateType::Unspecified => None,
+        }
+    }
+}
+
+/// Link quality and traffic of a single peer, e.g. for a network HUD.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PeerStats {
+    /// Round trip time of the selected candidate pair, or of the last
+    /// answered heartbeat while ICE has not measured one, see
+    /// [`crate::peer::Heartbeat`].
+    pub rtt: Option<Duration>,
+    pub bytes_sent: u64,
+    pub packets_sent: u64,
+    pub bytes_received: u64,
+    pub packets_received: u64,
+    /// Packets for the peer that could not be sent or did not fit into its
+    /// queue, see [`crate::peer::QueueLimits`].
+    pub dropped: u64,
+    /// Packets from the peer that were dropped because the application did not
+    /// receive them in time.
+    pub dropped_incoming: u64,
+    /// Packets from the peer without a valid frame header.
+    pub undecodable: u64,
+    /// Packets waiting in the socket for the peer.
+    pub queued: usize,
+    /// Bytes the data channels accepted but did not send yet.
+    pub buffered_amount: usize,
+    pub candidate_type: Option<CandidateType>,
+}
+
+/// Traffic counters, shared with the callbacks that receive packets.
+#[derive(Debug, Default)]
+pub(crate) struct PeerCounters {
+    bytes_sent: AtomicU64,
+    packets_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    packets_received: AtomicU64,
+    dropped: AtomicU64,
+    dropped_incoming: AtomicU64,
+    undecodable: AtomicU64,
+}
+
+impl PeerCounters {
+    pub(crate) fn sent(&self, bytes: usize) {
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.packets_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn received(&self, bytes: usize) {
+        self.bytes_received
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+        self.packets_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn dropped_incoming(&self) {
+        self.dropped_incoming.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn undecodable(&self) {
+        self.undecodable.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Stats with the counters filled in and everything else unknown.
+    pub(crate) fn stats(&self) -> PeerStats {
+        PeerStats {
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            packets_sent: self.packets_sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            packets_received: self.packets_received.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            dropped_incoming: self.dropped_incoming.load(Ordering::Relaxed),
+            undecodable: self.undecodable.load(Ordering::Relaxed),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PeerCounters, PeerStats};
+
+    #[test]
+    fn counters_add_up() {
+        let counters = PeerCounters::default();
+        counters.sent(10);
+        counters.sent(5);
+        counters.received(7);
+        counters.dropped();
+        counters.dropped_incoming();
+        counters.undecodable();
+        assert_eq!(
+            counters.stats(),
+            PeerStats {
+                bytes_sent: 15,
+                packets_sent: 2,
+                bytes_received: 7,
+                packets_received: 1,
+                dropped: 1,
+                dropped_incoming: 1,
+                undecodable: 1,
+                ..Default::default()
+            }
+        );
+    }
+}
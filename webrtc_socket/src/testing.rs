@@ -0,0 +1,74 @@
+//! Sockets that signal through a [`ChannelHub`], shared by the tests of the
+//! socket's features.
+
+use std::{future::Future, time::Duration};
+
+use futures_util::future::select_all;
+use tokio::{select, time};
+
+use crate::{peer::RtcConfigBuilder, signaller::ChannelHub, Packet, RawSocket, WebRTCSocket};
+
+pub(crate) const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `N` sockets of a new hub sorted by id, so every socket is polite to the
+/// ones before it.
+pub(crate) async fn hub_sockets<const N: usize>(config: RtcConfigBuilder) -> [WebRTCSocket; N] {
+    let config = config.ice_servers(vec![]).build();
+    let hub = ChannelHub::new();
+    let mut signallers: Vec<_> = (0..N).map(|_| hub.signaller()).collect();
+    signallers.sort_by_key(|signaller| signaller.id());
+    let mut sockets = Vec::with_capacity(N);
+    for signaller in signallers {
+        let socket = WebRTCSocket::with_signaller(config.clone(), signaller)
+            .await
+            .unwrap();
+        sockets.push(socket);
+    }
+    match sockets.try_into() {
+        Ok(sockets) => sockets,
+        Err(_) => unreachable!(),
+    }
+}
+
+/// Runs the sockets until `until` is done, panics if one of them stops first
+/// or `until` takes longer than `timeout`.
+pub(crate) async fn run_until<T>(
+    sockets: &mut [WebRTCSocket],
+    timeout: Duration,
+    until: impl Future<Output = T>,
+) -> T {
+    let runs = select_all(sockets.iter_mut().map(|socket| Box::pin(socket.run())));
+    select! {
+        (res, i, _) = runs => panic!("Socket {i} stopped: {res:?}"),
+        res = time::timeout(timeout, until) => res.expect("Timed out"),
+    }
+}
+
+/// Runs the sockets until each of them is ready to send to all the others.
+pub(crate) async fn connect(sockets: &mut [WebRTCSocket]) {
+    let others = sockets.len() - 1;
+    let mut ready: Vec<_> = sockets.iter().map(WebRTCSocket::ready_peers).collect();
+    let all_ready = async {
+        for ready_peers in &mut ready {
+            while ready_peers.borrow().len() < others {
+                ready_peers.changed().await.unwrap();
+            }
+        }
+    };
+    run_until(sockets, TIMEOUT, all_ready).await;
+}
+
+/// Sends the packet again until it arrives, e.g. while its channel is still
+/// opening.
+pub(crate) async fn resend_until_received(
+    from: &mut RawSocket,
+    to: &mut RawSocket,
+    packet: Packet,
+) -> Packet {
+    loop {
+        from.send_data(packet.clone()).unwrap();
+        if let Ok(received) = time::timeout(Duration::from_millis(100), to.recv()).await {
+            return received.unwrap();
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::{
+    mpsc::{error::TrySendError, Sender},
+    oneshot, watch,
+};
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::{
+    broadcast::Outgoing, error::Result, frame::PacketReceiver, message::StateMessage,
+    wire::DecodeErrors, Packet, PeerStats,
+};
+
+/// Carries the packets of a [`crate::GgrsSocket`] to the other peers.
+pub trait Transport {
+    /// Id the other peers know us by.
+    fn id(&self) -> Uuid;
+
+    /// Sends `packet` to the peer in its id, it is dropped if the peer is
+    /// unknown.
+    fn send(&self, packet: Packet);
+
+    /// GGRS packets received since the last call, never blocks.
+    fn receive(&self) -> Vec<Packet>;
+
+    /// Sorted ids of the peers that can be sent to, kept up to date while the
+    /// socket runs.
+    fn ready_peers(&self) -> &watch::Receiver<Vec<Uuid>>;
+
+    /// Asks the running socket for the ready peers.
+    fn request_ready_peers(&self) -> Result<oneshot::Receiver<Vec<Uuid>>>;
+
+    /// Counters for received input that was dropped.
+    fn decode_errors(&self) -> &DecodeErrors;
+
+    /// Latest stats of every peer, never blocks.
+    fn peer_stats(&self) -> HashMap<Uuid, PeerStats>;
+}
+
+/// Hands packets to the run loop of a [`crate::WebRTCSocket`] or to a
+/// [`crate::loopback::LoopbackNetwork`], which send them over data channels.
+///
+/// Clones share one queue of incoming packets.
+#[derive(Debug, Clone)]
+pub struct ChannelTransport {
+    id: Uuid,
+    in_ggrs_rx: Arc<Mutex<PacketReceiver>>,
+    out_data_tx: Sender<Outgoing>,
+    state_tx: Sender<StateMessage>,
+    ready_peers: watch::Receiver<Vec<Uuid>>,
+    decode_errors: Arc<DecodeErrors>,
+    stats: watch::Receiver<HashMap<Uuid, PeerStats>>,
+}
+
+impl ChannelTransport {
+    pub(crate) fn new(
+        id: Uuid,
+        in_ggrs_rx: PacketReceiver,
+        out_data_tx: Sender<Outgoing>,
+        state_tx: Sender<StateMessage>,
+        ready_peers: watch::Receiver<Vec<Uuid>>,
+        decode_errors: Arc<DecodeErrors>,
+        stats: watch::Receiver<HashMap<Uuid, PeerStats>>,
+    ) -> Self {
+        Self {
+            id,
+            in_ggrs_rx: Arc::new(Mutex::new(in_ggrs_rx)),
+            out_data_tx,
+            state_tx,
+            ready_peers,
+            decode_errors,
+            stats,
+        }
+    }
+}
+
+impl Transport for ChannelTransport {
+    fn id(&self) -> Uuid {
+        self.id
+    }
+
+    fn send(&self, packet: Packet) {
+        let id = packet.id;
+        if let Err(TrySendError::Full(_)) = self.out_data_tx.try_send(packet.into()) {
+            // GGRS sends its inputs again until they are acknowledged
+            debug!("Dropping GGRS packet for {id}, the socket is not keeping up");
+        }
+    }
+
+    fn receive(&self) -> Vec<Packet> {
+        let mut in_ggrs_rx = self.in_ggrs_rx.lock().unwrap();
+        std::iter::from_fn(|| in_ggrs_rx.try_recv().ok()).collect()
+    }
+
+    fn ready_peers(&self) -> &watch::Receiver<Vec<Uuid>> {
+        &self.ready_peers
+    }
+
+    fn request_ready_peers(&self) -> Result<oneshot::Receiver<Vec<Uuid>>> {
+        let (tx, rx) = oneshot::channel();
+        self.state_tx.try_send(StateMessage::ReadyPeers(tx))?;
+        Ok(rx)
+    }
+
+    fn decode_errors(&self) -> &DecodeErrors {
+        &self.decode_errors
+    }
+
+    fn peer_stats(&self) -> HashMap<Uuid, PeerStats> {
+        self.stats.borrow().clone()
+    }
+}
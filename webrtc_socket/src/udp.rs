@@ -0,0 +1,561 @@
+//! GGRS over plain UDP for native clients on open networks. Peers still meet
+//! through a [`Signaller`] and are ordered like WebRTC peers, but only exchange
+//! their addresses instead of negotiating data channels.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    sync::{Arc, Mutex, RwLock},
+};
+
+use futures_util::future::BoxFuture;
+use tokio::{
+    select,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        oneshot, watch, Notify,
+    },
+};
+use tracing::{debug, info, trace, warn};
+use uuid::Uuid;
+
+use crate::{
+    error::{Error, Result},
+    event::Subscribers,
+    frame::{self, MessageKind},
+    message::{Message, PeerMessage, StateMessage},
+    peer::{Channel, RtcConfig},
+    signaller::{self, MatchmakerSignaller, SignalingEvent, SignalingSession, Signaller},
+    stats::PeerCounters,
+    transport::Transport,
+    wire::{self, DecodeErrors, DecodeSource},
+    CloseHandle, EventStream, GgrsSocket, Packet, Payload, PeerStats, SocketEvent,
+};
+
+/// Where a [`UdpPeerSocket`] receives packets.
+#[derive(Debug, Clone)]
+pub struct UdpConfig {
+    /// Any interface and a random port by default.
+    pub bind_addr: SocketAddr,
+    /// IP the other peers send to. Without it the matchmaker fills in the IP
+    /// it sees us connect from, other signallers need it.
+    pub public_ip: Option<IpAddr>,
+}
+
+impl Default for UdpConfig {
+    fn default() -> Self {
+        Self {
+            bind_addr: (Ipv4Addr::UNSPECIFIED, 0).into(),
+            public_ip: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct UdpPeer {
+    addr: SocketAddr,
+    counters: Arc<PeerCounters>,
+}
+
+/// The peers we can send to.
+type Directory = Arc<RwLock<HashMap<Uuid, UdpPeer>>>;
+
+/// Sends and receives the GGRS packets of a [`UdpPeerSocket`] on the calling
+/// thread, without going through its run loop.
+///
+/// Clones share the socket, each packet is received by only one of them.
+#[derive(Debug, Clone)]
+pub struct UdpTransport {
+    id: Uuid,
+    socket: Arc<UdpSocket>,
+    /// Shared with the clones, so [`Transport::receive`] does not allocate.
+    buf: Arc<Mutex<Vec<u8>>>,
+    peers: Directory,
+    ready_peers: watch::Receiver<Vec<Uuid>>,
+    decode_errors: Arc<DecodeErrors>,
+}
+
+impl UdpTransport {
+    /// Where packets for the peer are sent, once it told us.
+    pub fn peer_addr(&self, id: Uuid) -> Option<SocketAddr> {
+        self.peers.read().unwrap().get(&id).map(|peer| peer.addr)
+    }
+
+    fn sender(&self, addr: SocketAddr) -> Option<(Uuid, Arc<PeerCounters>)> {
+        let peers = self.peers.read().unwrap();
+        peers
+            .iter()
+            .find(|(_, peer)| peer.addr == addr)
+            .map(|(&id, peer)| (id, peer.counters.clone()))
+    }
+}
+
+impl Transport for UdpTransport {
+    fn id(&self) -> Uuid {
+        self.id
+    }
+
+    fn send(&self, packet: Packet) {
+        let peers = self.peers.read().unwrap();
+        let peer = match peers.get(&packet.id) {
+            Some(peer) => peer,
+            None => return,
+        };
+        let frame = frame::encode(packet.kind, &packet.payload);
+        match self.socket.send_to(&frame, peer.addr) {
+            Ok(_) => peer.counters.sent(frame.len()),
+            Err(e) => {
+                peer.counters.dropped();
+                warn!("Failed to send UDP packet to {}: {e}", packet.id);
+            }
+        }
+    }
+
+    fn receive(&self) -> Vec<Packet> {
+        let mut buf = self.buf.lock().unwrap();
+        let mut packets = vec![];
+        loop {
+            let (len, from) = match self.socket.recv_from(&mut buf) {
+                Ok(res) => res,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                // Windows reports unreachable peers on the next receive
+                Err(e) if e.kind() == io::ErrorKind::ConnectionReset => continue,
+                Err(e) => {
+                    warn!("Failed to receive UDP packet: {e}");
+                    break;
+                }
+            };
+            let (id, counters) = match self.sender(from) {
+                Some(sender) => sender,
+                None => {
+                    trace!("Dropping UDP packet from unknown address {from}");
+                    continue;
+                }
+            };
+            counters.received(len);
+            match frame::decode(Payload::copy_from_slice(&buf[..len])) {
+                Some((MessageKind::Ggrs, payload)) => {
+                    let packet =
+                        Packet::with_kind(id, Channel::Unreliable, MessageKind::Ggrs, payload);
+                    packets.push(packet.received());
+                }
+                _ => {
+                    counters.undecodable();
+                    self.decode_errors.record(DecodeSource::Frame);
+                    warn!("Dropping malformed UDP packet from {id}");
+                }
+            }
+        }
+        packets
+    }
+
+    fn ready_peers(&self) -> &watch::Receiver<Vec<Uuid>> {
+        &self.ready_peers
+    }
+
+    fn request_ready_peers(&self) -> Result<oneshot::Receiver<Vec<Uuid>>> {
+        // The directory is shared, there is no need to ask the run loop
+        let mut ids: Vec<_> = self.peers.read().unwrap().keys().copied().collect();
+        ids.sort();
+        let (tx, rx) = oneshot::channel();
+        let _ = tx.send(ids);
+        Ok(rx)
+    }
+
+    fn decode_errors(&self) -> &DecodeErrors {
+        &self.decode_errors
+    }
+
+    fn peer_stats(&self) -> HashMap<Uuid, PeerStats> {
+        let peers = self.peers.read().unwrap();
+        peers
+            .iter()
+            .map(|(&id, peer)| (id, peer.counters.stats()))
+            .collect()
+    }
+}
+
+/// Finds peers through a [`Signaller`] like a [`crate::WebRTCSocket`], but
+/// sends GGRS packets over plain UDP. There is no NAT traversal, every peer
+/// has to be reachable at the address it announces.
+pub struct UdpPeerSocket {
+    id: Uuid,
+    rtc_config: RtcConfig,
+    signaller: Arc<dyn Signaller + Send + Sync>,
+    session: Box<dyn SignalingSession + Send>,
+    socket: Arc<UdpSocket>,
+    /// What we tell the other peers.
+    addr: SocketAddr,
+    /// Peers logged in to the signaling server, with or without an address.
+    joined: HashSet<Uuid>,
+    peers: Directory,
+    state_tx: Sender<StateMessage>,
+    state_rx: Receiver<StateMessage>,
+    close_requested: Arc<Notify>,
+    subscribers: Subscribers,
+    ready_peers_tx: watch::Sender<Vec<Uuid>>,
+    decode_errors: Arc<DecodeErrors>,
+}
+
+impl std::fmt::Debug for UdpPeerSocket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UdpPeerSocket")
+            .field("id", &self.id)
+            .field("addr", &self.addr)
+            .field("peers", &self.peers)
+            .finish()
+    }
+}
+
+impl UdpPeerSocket {
+    /// Logs in to the matchmaker, see [`MatchmakerSignaller`].
+    pub async fn new(rtc_config: RtcConfig, config: UdpConfig) -> Result<Self> {
+        let signaller = MatchmakerSignaller::new(rtc_config.clone());
+        UdpPeerSocket::with_signaller(rtc_config, config, signaller).await
+    }
+
+    /// Logs in to another signaling backend, `rtc_config` still provides the
+    /// reconnect backoff.
+    pub async fn with_signaller<S>(
+        rtc_config: RtcConfig,
+        config: UdpConfig,
+        signaller: S,
+    ) -> Result<Self>
+    where
+        S: Signaller + Send + Sync + 'static,
+    {
+        let socket = UdpSocket::bind(config.bind_addr)?;
+        socket.set_nonblocking(true)?;
+        let local_addr = socket.local_addr()?;
+        let addr = SocketAddr::new(
+            config.public_ip.unwrap_or(local_addr.ip()),
+            local_addr.port(),
+        );
+        let (id, session) = signaller.connect().await?;
+        let (state_tx, state_rx) = mpsc::channel(rtc_config.queue_limits.control);
+        let (ready_peers_tx, _) = watch::channel(vec![]);
+        Ok(Self {
+            id,
+            rtc_config,
+            signaller: Arc::new(signaller),
+            session,
+            socket: Arc::new(socket),
+            addr,
+            joined: Default::default(),
+            peers: Default::default(),
+            state_tx,
+            state_rx,
+            close_requested: Default::default(),
+            subscribers: Default::default(),
+            ready_peers_tx,
+            decode_errors: Default::default(),
+        })
+    }
+
+    /// Id the signaling server assigned to us.
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    /// Address we announce to the other peers.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn transport(&self) -> UdpTransport {
+        UdpTransport {
+            id: self.id,
+            socket: self.socket.clone(),
+            buf: Arc::new(Mutex::new(vec![0; wire::MAX_MESSAGE_SIZE as usize + 1])),
+            peers: self.peers.clone(),
+            ready_peers: self.ready_peers_tx.subscribe(),
+            decode_errors: self.decode_errors.clone(),
+        }
+    }
+
+    /// Handle for GGRS, all handles share the same socket.
+    pub fn ggrs_socket(&self) -> GgrsSocket<UdpTransport> {
+        GgrsSocket::with_transport(self.transport())
+    }
+
+    /// Stream of [`SocketEvent`]s, peers are ready once we know their address.
+    pub fn subscribe(&mut self) -> EventStream {
+        self.subscribers.subscribe()
+    }
+
+    /// Handle to close the socket while [`UdpPeerSocket::run`] is running.
+    pub fn close_handle(&self) -> CloseHandle {
+        CloseHandle {
+            close_requested: self.close_requested.clone(),
+        }
+    }
+
+    /// Counters for input from peers or the signaling server that was dropped.
+    pub fn decode_errors(&self) -> Arc<DecodeErrors> {
+        self.decode_errors.clone()
+    }
+
+    /// Exchanges addresses with the other peers until the socket is closed.
+    /// GGRS packets don't pass through here, but peers are only found while it
+    /// runs.
+    pub async fn run(&mut self) -> Result<()> {
+        debug!("UDP run() started");
+        self.subscribers
+            .emit(SocketEvent::Connected { id: self.id });
+
+        let mut reconnecting: Option<
+            BoxFuture<'static, Result<(Uuid, Box<dyn SignalingSession + Send>)>>,
+        > = None;
+        let mut outgoing = VecDeque::new();
+        let close_requested = self.close_requested.clone();
+        loop {
+            if reconnecting.is_none() {
+                if let Some(msg) = outgoing.pop_front() {
+                    trace!(?msg);
+                    if let Err(e) = self.session.send(msg.clone()).await {
+                        warn!("Signaling connection lost: {e}");
+                        outgoing.push_front(msg);
+                        reconnecting = Some(self.signaling_lost());
+                    }
+                    continue;
+                }
+            }
+            select! {
+                event = self.session.recv(), if reconnecting.is_none() => match event {
+                    Ok(Some(SignalingEvent::Message(msg))) => {
+                        trace!(?msg);
+                        outgoing.extend(self.handle_message(msg));
+                        self.publish_ready_peers();
+                    }
+                    Ok(Some(SignalingEvent::Invalid(reason))) => {
+                        self.decode_errors.record(DecodeSource::Signaling);
+                        warn!("Dropping malformed signaling message: {reason}");
+                    }
+                    Ok(None) => {
+                        self.forget_peers();
+                        break;
+                    }
+                    Err(e) => {
+                        warn!("Signaling connection lost: {e}");
+                        reconnecting = Some(self.signaling_lost());
+                    }
+                },
+                res = async { reconnecting.as_mut().unwrap().await }, if reconnecting.is_some() => {
+                    reconnecting = None;
+                    let (id, session) = res?;
+                    if id != self.id {
+                        return Err(Error::Protocol(format!(
+                            "Resumed session has id {id} instead of {}",
+                            self.id
+                        )));
+                    }
+                    self.session = session;
+                    info!("Signaling connection restored");
+                    self.subscribers.emit(SocketEvent::SignalingRestored);
+                }
+                _ = close_requested.notified() => {
+                    self.close().await?;
+                    break;
+                }
+                Some(msg) = self.state_rx.recv() => match msg {
+                    StateMessage::ReadyPeers(tx) => {
+                        let _ = tx.send(self.ready_peers_tx.borrow().clone());
+                    }
+                    StateMessage::Close => {
+                        self.close().await?;
+                        break;
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Forgets all peers and tells the signaling server that we are leaving.
+    pub async fn close(&mut self) -> Result<()> {
+        debug!("Closing UdpPeerSocket {}", self.id);
+        self.forget_peers();
+        self.session.close().await
+    }
+
+    fn signaling_lost(
+        &mut self,
+    ) -> BoxFuture<'static, Result<(Uuid, Box<dyn SignalingSession + Send>)>> {
+        self.subscribers.emit(SocketEvent::SignalingLost);
+        signaller::reconnect(self.signaller.clone(), self.rtc_config.reconnect.clone())
+    }
+
+    /// Our address for `peer_id`.
+    fn announce(&self, peer_id: Uuid) -> PeerMessage {
+        PeerMessage {
+            peer_id,
+            content: Message::UdpAddress {
+                id: self.id,
+                addr: self.addr,
+            },
+        }
+    }
+
+    /// Updates the peers and returns what has to be sent to them.
+    fn handle_message(&mut self, msg: Message) -> Vec<PeerMessage> {
+        match msg {
+            Message::Peers { ids } => {
+                // Peers that left while our signaling connection was down
+                let left: Vec<_> = self
+                    .joined
+                    .iter()
+                    .filter(|id| !ids.contains(id))
+                    .copied()
+                    .collect();
+                for id in left {
+                    self.forget_peer(id);
+                }
+                for &id in ids.iter() {
+                    if self.joined.insert(id) {
+                        self.subscribers.emit(SocketEvent::PeerJoined { id });
+                    }
+                }
+                // Sent again after a reconnect, in case ours got lost
+                ids.into_iter().map(|id| self.announce(id)).collect()
+            }
+            Message::NewPeer { id } => {
+                self.joined.insert(id);
+                self.subscribers.emit(SocketEvent::PeerJoined { id });
+                vec![self.announce(id)]
+            }
+            Message::PeerDisconnected { id } => {
+                debug!("Received PeerDisconnected msg for: {id}");
+                self.forget_peer(id);
+                vec![]
+            }
+            Message::UdpAddress { id, addr } => {
+                debug!("Peer {id} receives UDP packets at {addr}");
+                self.joined.insert(id);
+                let mut peers = self.peers.write().unwrap();
+                // A new address keeps the counters of the peer
+                let changed = match peers.get_mut(&id) {
+                    Some(peer) => std::mem::replace(&mut peer.addr, addr) != addr,
+                    None => {
+                        let counters = Default::default();
+                        peers.insert(id, UdpPeer { addr, counters });
+                        true
+                    }
+                };
+                drop(peers);
+                if changed {
+                    self.subscribers.emit(SocketEvent::PeerReady { id });
+                }
+                vec![]
+            }
+            msg => {
+                debug!("Ignoring {msg:?}, UDP peers don't negotiate connections");
+                vec![]
+            }
+        }
+    }
+
+    fn forget_peer(&mut self, id: Uuid) {
+        if self.joined.remove(&id) {
+            self.peers.write().unwrap().remove(&id);
+            self.subscribers.emit(SocketEvent::PeerLeft { id });
+        }
+    }
+
+    fn forget_peers(&mut self) {
+        self.joined.clear();
+        self.peers.write().unwrap().clear();
+        self.publish_ready_peers();
+    }
+
+    /// Updates the snapshot the [`UdpTransport`]s hand out.
+    fn publish_ready_peers(&self) {
+        let mut ids: Vec<_> = self.peers.read().unwrap().keys().copied().collect();
+        ids.sort();
+        if *self.ready_peers_tx.borrow() != ids {
+            self.ready_peers_tx.send_replace(ids);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::Ipv4Addr, time::Duration};
+
+    use ggrs::{SessionBuilder, SessionState};
+    use tokio::{select, time};
+    use uuid::Uuid;
+
+    use super::{UdpConfig, UdpPeerSocket};
+    use crate::{peer::RtcConfig, signaller::ChannelHub};
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    struct TestConfig;
+
+    impl ggrs::Config for TestConfig {
+        type Input = u8;
+        type State = u8;
+        type Address = Uuid;
+    }
+
+    #[tokio::test]
+    async fn ggrs_sessions_synchronize_over_udp() {
+        let hub = ChannelHub::new();
+        let config = UdpConfig {
+            public_ip: Some(Ipv4Addr::LOCALHOST.into()),
+            ..Default::default()
+        };
+        let mut alice =
+            UdpPeerSocket::with_signaller(RtcConfig::default(), config.clone(), hub.signaller())
+                .await
+                .unwrap();
+        let mut bob = UdpPeerSocket::with_signaller(RtcConfig::default(), config, hub.signaller())
+            .await
+            .unwrap();
+        let sockets = [alice.ggrs_socket(), bob.ggrs_socket()];
+        let players = async {
+            for socket in sockets.iter() {
+                socket.wait_for_players(2, TIMEOUT).await.unwrap();
+            }
+        };
+        select! {
+            res = alice.run() => panic!("alice stopped: {res:?}"),
+            res = bob.run() => panic!("bob stopped: {res:?}"),
+            _ = players => {}
+        }
+        assert_eq!(
+            sockets[1].transport().peer_addr(alice.id()),
+            Some(alice.addr())
+        );
+        let transports = [alice.transport(), bob.transport()];
+
+        // Packets go straight to the socket, the run loops are not needed
+        let mut sessions: Vec<_> = sockets
+            .into_iter()
+            .map(|socket| {
+                let mut builder = SessionBuilder::<TestConfig>::new().with_num_players(2);
+                for (handle, player) in socket.players_snapshot().into_iter().enumerate() {
+                    builder = builder.add_player(player, handle).unwrap();
+                }
+                builder.start_p2p_session(socket).unwrap()
+            })
+            .collect();
+        for _ in 0..200 {
+            for session in sessions.iter_mut() {
+                session.poll_remote_clients();
+            }
+            if sessions
+                .iter()
+                .all(|s| s.current_state() == SessionState::Running)
+            {
+                let stats = &transports[0].peer_stats()[&bob.id()];
+                assert!(stats.packets_sent > 0 && stats.packets_received > 0);
+                assert_eq!(stats.undecodable, 0);
+                return;
+            }
+            time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("GGRS sessions did not synchronize");
+    }
+}
@@ -0,0 +1,108 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use bincode::Options;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Upper bound for a single bincode encoded message. Anything larger is
+/// rejected before allocating, so a peer cannot make us reserve huge buffers
+/// with a forged length prefix.
+pub const MAX_MESSAGE_SIZE: u64 = 64 * 1024;
+
+fn options() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .reject_trailing_bytes()
+        .with_limit(MAX_MESSAGE_SIZE)
+}
+
+pub fn serialize<T: Serialize + ?Sized>(value: &T) -> bincode::Result<Vec<u8>> {
+    options().serialize(value)
+}
+
+pub fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> bincode::Result<T> {
+    options().deserialize(bytes)
+}
+
+/// Where a piece of rejected input was received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeSource {
+    /// A data channel packet without a valid [`crate::frame::MessageKind`] header.
+    Frame,
+    /// A GGRS message that failed to deserialize.
+    Ggrs,
+    /// An application message that failed to deserialize.
+    App,
+    /// A message from the signaling backend that could not be decoded.
+    Signaling,
+}
+
+/// Counters for input that was dropped because it could not be decoded.
+#[derive(Debug, Default)]
+pub struct DecodeErrors {
+    frame: AtomicU64,
+    ggrs: AtomicU64,
+    app: AtomicU64,
+    signaling: AtomicU64,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeErrorCounts {
+    pub frame: u64,
+    pub ggrs: u64,
+    pub app: u64,
+    pub signaling: u64,
+}
+
+impl DecodeErrors {
+    pub(crate) fn record(&self, source: DecodeSource) {
+        let counter = match source {
+            DecodeSource::Frame => &self.frame,
+            DecodeSource::Ggrs => &self.ggrs,
+            DecodeSource::App => &self.app,
+            DecodeSource::Signaling => &self.signaling,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn counts(&self) -> DecodeErrorCounts {
+        DecodeErrorCounts {
+            frame: self.frame.load(Ordering::Relaxed),
+            ggrs: self.ggrs.load(Ordering::Relaxed),
+            app: self.app.load(Ordering::Relaxed),
+            signaling: self.signaling.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{deserialize, serialize, DecodeErrors, DecodeSource, MAX_MESSAGE_SIZE};
+
+    #[test]
+    fn forged_length_prefix_is_rejected() {
+        let forged = u64::MAX.to_le_bytes();
+        assert!(deserialize::<Vec<u8>>(&forged).is_err());
+    }
+
+    #[test]
+    fn oversized_and_trailing_input_is_rejected() {
+        let oversized = vec![0u8; MAX_MESSAGE_SIZE as usize];
+        assert!(serialize(&oversized).is_err());
+
+        let mut bytes = serialize(&7u32).unwrap();
+        bytes.push(0);
+        assert!(deserialize::<u32>(&bytes).is_err());
+    }
+
+    #[test]
+    fn errors_are_counted_per_source() {
+        let errors = DecodeErrors::default();
+        errors.record(DecodeSource::Ggrs);
+        errors.record(DecodeSource::Ggrs);
+        errors.record(DecodeSource::Signaling);
+        let counts = errors.counts();
+        assert_eq!(counts.ggrs, 2);
+        assert_eq!(counts.signaling, 1);
+        assert_eq!(counts.frame + counts.app, 0);
+    }
+}